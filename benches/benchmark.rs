@@ -1,12 +1,15 @@
+use std::rc::Rc;
+
 use criterion::{black_box, Criterion, criterion_group, criterion_main};
 
 use battlesnake_alphazero::alpha_zero_model::AlphaZeroModel;
 use battlesnake_alphazero::canonical_board::{CanonicalBoard, flip_board_horizontal, rotate_board, rotate_policy};
 use battlesnake_alphazero::game::{Board, BoardInit, CanCanonical};
 use battlesnake_alphazero::mcts::MCTS;
+use battlesnake_alphazero::terminal::TerminalConfig;
 
-pub fn get_canonical_board(min_health_threshold:u8) -> CanonicalBoard {
-    Board::init_start_of_game_board().as_canonical(1i32, min_health_threshold)
+pub fn get_canonical_board(min_health_threshold:u8) -> CanonicalBoard<Board> {
+    Board::init_start_of_game_board().as_canonical(1i32, TerminalConfig::new(min_health_threshold))
 }
 
 
@@ -39,10 +42,10 @@ pub fn bench_canonical_board_to_array_board(c: &mut Criterion) {
     }));
 }
 
-pub fn bench_canonical_board_to_hashmap_bytes(c: &mut Criterion) {
+pub fn bench_canonical_board_to_hashmap_key(c: &mut Criterion) {
     let canonical_board = get_canonical_board(80);
-    c.bench_function("bench_canonical_board_to_hashmap_bytes", |b| b.iter(|| {
-        canonical_board.to_hashmap_bytes();
+    c.bench_function("bench_canonical_board_to_hashmap_key", |b| b.iter(|| {
+        canonical_board.to_hashmap_key();
     }));
 }
 
@@ -90,9 +93,9 @@ pub fn bench_rotate_policy(c: &mut Criterion) {
 
 pub fn bench_mcts(c: &mut Criterion) {
     let canonical_board = get_canonical_board(80);
-    let model = AlphaZeroModel::new(128);
+    let model = Rc::new(AlphaZeroModel::new(128, 11));
     c.bench_function("bench_mcts", |b| b.iter(|| {
-        let mut mcts = MCTS::new(&model, 4.0, 400);
+        let mut mcts = MCTS::<Board>::new(&model, 4.0, 400);
         mcts.get_action_prob(black_box(&canonical_board), black_box(0.0));
     }));
 }
@@ -102,7 +105,7 @@ pub fn bench_alphazero_get_action_probs(c: &mut Criterion) {
     let canonical_board = get_canonical_board(80);
     let model = AlphaZeroModel::default();
     c.bench_function("bench_alphazero_get_action_probs", |b| b.iter(|| {
-        model.clone().predict(black_box(&canonical_board));
+        model.predict(black_box(&canonical_board));
     }));
 }
 
@@ -121,7 +124,7 @@ criterion_group!(
     // bench_canonical_board_to_tensor,
     //  bench_canonical_board_get_next_state,
     //  bench_canonical_board_to_array_board,
-      bench_canonical_board_to_hashmap_bytes,
+      bench_canonical_board_to_hashmap_key,
       bench_canonical_board_to_hashmap_string,
     //  bench_canonical_board_mirroring_and_rotation,
      //bench_rotate_array_board,
@@ -1,15 +1,21 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use indicatif::ProgressStyle;
-use itertools::{Itertools, multiunzip};
-use ndarray::{arr1, arr2, arr3};
-use tch::{autocast, Device, nn, no_grad, Tensor};
-use tch::nn::{Adam, OptimizerConfig};
+use itertools::Itertools;
+use rand::Rng;
+use tch::{autocast, CModule, Device, Kind, nn, no_grad, TchError, Tensor};
 
+use crate::Args;
 use crate::canonical_board::CanonicalBoard;
-use crate::config::NUM_CHANNELS;
-use crate::game::Sample;
-use crate::neural_network::NeuralNetwork;
+use crate::config::{AUX_LENGTH_SCALE, AUX_TURNS_SCALE, BOARD_SIZE, NUM_CHANNELS};
+use crate::data_loader::{Batch, DataLoader};
+use crate::dataset::Dataset;
+use crate::game::GameBoard;
+use crate::metrics::MetricsLogger;
+use crate::neural_network::{AuxConfig, AuxOutputs, NeuralNetwork};
+use crate::optimizer::Optimizer;
 use crate::utils::AverageMeter;
 
 pub fn get_base_device() -> Device {
@@ -21,100 +27,381 @@ pub fn get_base_var_store() -> nn::VarStore {
     nn::VarStore::new(get_base_device())
 }
 
-pub type SampleZipped = (Vec<[[f32; 11]; 11]>, Vec<[f32; 4]>, Vec<f32>);
+#[derive(Clone, Copy, Debug)]
+pub struct TrainConfig {
+    pub epochs: i32,
+    pub batch_size: usize,
+    // stop once the validation loss did not improve for this many epochs
+    pub early_stopping_patience: Option<usize>,
+    // batches built ahead on a separate thread, 0 disables the prefetch thread
+    pub prefetch_batches: usize,
+}
 
+impl TrainConfig {
+    pub fn from_args(args: &Args) -> Self {
+        TrainConfig {
+            epochs: args.num_epochs,
+            batch_size: args.batch_size,
+            early_stopping_patience: args.early_stopping_patience,
+            prefetch_batches: args.prefetch_batches,
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ValidationStats {
+    pub pi_loss: f32,
+    pub v_loss: f32,
+    // share of samples whose most likely move is the target's most likely move
+    pub policy_accuracy: f32,
+    // share of decided samples whose predicted value has the sign of the outcome
+    pub value_accuracy: f32,
+}
+
+impl ValidationStats {
+    pub fn loss(&self) -> f32 {
+        self.pi_loss + self.v_loss
+    }
+}
+
+
+// Read only handle on a model, MCTS instances holding it share the weights instead of copying them.
+// tch tensors are not Sync, the handle stays on the thread that made it
+pub type SharedModel = Rc<AlphaZeroModel>;
 
 pub struct AlphaZeroModel {
     vs: nn::VarStore,
     nnet: NeuralNetwork,
     num_channels: i64,
+    board_size: i64,
+    aux: AuxConfig,
 }
 
 
 impl AlphaZeroModel {
-    pub fn new(num_channels:i64) -> Self {
+    pub fn new(num_channels:i64, board_size: i64) -> Self {
+        Self::with_aux(num_channels, board_size, AuxConfig::default())
+    }
+
+    // Model with the auxiliary heads of aux, trained along with the policy and the value
+    pub fn with_aux(num_channels:i64, board_size: i64, aux: AuxConfig) -> Self {
         let vs = get_base_var_store();
-        let nnet = NeuralNetwork::new(&vs.root(),num_channels);
+        let nnet = NeuralNetwork::new(&vs.root(),num_channels, board_size, aux);
         Self {
             vs,
             nnet,
             num_channels,
+            board_size,
+            aux,
         }
     }
 
-    pub fn train(&self, samples: Vec<Sample>, learning_rate: f64, epochs: i32, batch_size: usize) {
-        let mut optimizer = Adam::default().build(&self.vs, learning_rate).unwrap();
+    // Trace the network in evaluation mode as a TorchScript module taking boards of shape [N, size, size] and returning
+    // the log policies, the values and, with the opponent policy head, the opponent log policies.
+    // libtorch has no ONNX exporter, the module can be converted with torch.onnx in Python. See TorchScriptModel to play it
+    pub fn export_torchscript(&self, path: &Path) -> Result<(), TchError> {
+        let input = Tensor::zeros([1, self.board_size, self.board_size], (Kind::Float, get_base_device()));
+        let with_opponent = self.has_opponent_policy();
+        let module = no_grad(|| CModule::create_by_tracing("AlphaZeroModel", "forward", &[input], &mut |inputs| {
+            let (pi, v, aux) = self.nnet.forward_aux(&inputs[0], false);
+            match aux.opponent_pi {
+                Some(opponent_pi) if with_opponent => vec![pi, v, opponent_pi],
+                _ => vec![pi, v],
+            }
+        }))?;
+        module.save(path)
+    }
+
+    pub fn board_size(&self) -> i64 {
+        self.board_size
+    }
+
+    // Shared copy of the current weights, training self afterwards leaves it unchanged
+    pub fn snapshot(&self) -> SharedModel {
+        let mut vs = get_base_var_store();
+        // copy only fills the variables the network created in the new store
+        let nnet = NeuralNetwork::new(&vs.root(), self.num_channels, self.board_size, self.aux);
+        vs.copy(&self.vs).unwrap();
+        Rc::new(Self {
+            vs,
+            nnet,
+            num_channels: self.num_channels,
+            board_size: self.board_size,
+            aux: self.aux,
+        })
+    }
+
+    // All variables, batch norm statistics included, sharing their storage with the model
+    pub fn named_variables(&self) -> HashMap<String, Tensor> {
+        self.vs.variables()
+    }
+
+    // Trainable variables sorted by name, sharing their storage with the model
+    pub fn named_trainable_variables(&self) -> Vec<(String, Tensor)> {
+        self.vs.variables().into_iter()
+            .filter(|(_, var)| var.requires_grad())
+            .sorted_by(|(a, _), (b, _)| a.cmp(b))
+            .collect()
+    }
+
+    pub fn train<D: Dataset>(&mut self, loader: &DataLoader<D>, validation: &[usize], optimizer: &mut Optimizer, config: TrainConfig, metrics: &mut MetricsLogger, rng: &mut impl Rng) {
         let mut pi_losses = AverageMeter::default();
         let mut v_losses = AverageMeter::default();
-        let pb = indicatif::ProgressBar::new(epochs as u64);
+        let pb = indicatif::ProgressBar::new(config.epochs as u64);
         pb.set_style(ProgressStyle::default_bar()
             .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>7}/{len:7} {msg} ({eta})")
             .unwrap()
             .progress_chars("#>-"));
 
-        let batch_count = samples.len() / batch_size;
-
+        let batch_count = loader.batch_count();
         let base_device = get_base_device();
-        for _ in 0..epochs {
-            for i in 0..batch_count {
+
+        // validation loss and weights of the best epoch so far
+        let mut best: Option<(f32, HashMap<String, Tensor>)> = None;
+        let mut epochs_without_improvement = 0;
+        for epoch in 0..config.epochs {
+            let mut epoch_pi_losses = AverageMeter::default();
+            let mut epoch_v_losses = AverageMeter::default();
+            let mut epoch_entropies = AverageMeter::default();
+            for (i, batch) in loader.epoch(rng).enumerate() {
                 optimizer.zero_grad();
 
-                let ids = rand::seq::index::sample(&mut rand::thread_rng(), samples.len(), batch_size).into_vec();
-                let (boards, pi, value): SampleZipped = multiunzip(ids.iter().map(|&i| samples[i]).collect_vec());
-
-                let (s, l_pi, l_v, total_loss) = autocast(true, ||{
-                    let mut s = Tensor::try_from(arr3(&boards)).unwrap();
-                    let mut target_pis = Tensor::try_from(arr2(&pi)).unwrap();
-                    let mut target_vs = Tensor::try_from(arr1(&value)).unwrap();
-                    if base_device.is_cuda() {
-                        s = s.contiguous().to_device(base_device);
-                        target_pis = target_pis.contiguous().to_device(base_device);
-                        target_vs = target_vs.contiguous().to_device(base_device);
-                    }
-                    let (out_pi, out_v) = self.nnet.forward(&s, true);
+                let batch = batch.to_device(base_device);
+                let (l_pi, l_v, entropy, aux_losses, total_loss) = autocast(true, ||{
+                    let (out_pi, out_v, out_aux) = self.nnet.forward_aux(&batch.boards, true);
                     //println!("Out Pi: {:?}", out_pi.size());
                     //println!("Out V: {:?}", out_v.size());
-                    let l_pi = self.loss_pi(&target_pis, &out_pi);
-                    let l_v = self.loss_v(&target_vs, &out_v);
-                    let total_loss = l_pi.copy() + l_v.copy();
-                    (s, l_pi, l_v, total_loss)
+                    let l_pi = self.loss_pi(&batch.pis, &out_pi);
+                    let l_v = self.loss_v(&batch.values, &out_v);
+                    let entropy = no_grad(|| self.policy_entropy(&out_pi));
+                    let aux_losses = self.aux_losses(&batch, &out_aux);
+                    let total_loss = aux_losses.iter().fold(l_pi.copy() + l_v.copy(), |total, (_, weight, loss)| total + loss * *weight);
+                    (l_pi, l_v, entropy, aux_losses, total_loss)
                 });
 
 
                 let f32_l_pi = f32::try_from(l_pi).unwrap();
                 let f32_l_v = f32::try_from(l_v).unwrap();
-                let b_size = usize::try_from(s.size()[0]).unwrap();
+                let f32_entropy = f32::try_from(entropy).unwrap();
+                let b_size = batch.len();
 
                 pi_losses.update(f32_l_pi, b_size);
                 v_losses.update(f32_l_v, b_size);
+                epoch_pi_losses.update(f32_l_pi, b_size);
+                epoch_v_losses.update(f32_l_v, b_size);
+                epoch_entropies.update(f32_entropy, b_size);
                 if i % 100 == 0 {
                     pb.set_message(format!("{}/{} pi_loss: {} v_loss: {}", i, batch_count, pi_losses, v_losses));
                 }
 
                 total_loss.backward();
+                let grad_norm = self.grad_norm();
+                let learning_rate = optimizer.learning_rate();
                 optimizer.step();
+                let mut batch_values = vec![
+                    ("pi_loss", f32_l_pi as f64),
+                    ("v_loss", f32_l_v as f64),
+                    ("entropy", f32_entropy as f64),
+                    ("grad_norm", grad_norm),
+                    ("learning_rate", learning_rate),
+                ];
+                batch_values.extend(aux_losses.iter().map(|(name, _, loss)| (*name, loss.double_value(&[]))));
+                metrics.log_batch(&batch_values);
             }
+            let mut epoch_values = vec![
+                ("pi_loss", epoch_pi_losses.avg() as f64),
+                ("v_loss", epoch_v_losses.avg() as f64),
+                ("entropy", epoch_entropies.avg() as f64),
+                ("learning_rate", optimizer.learning_rate()),
+            ];
+            if !validation.is_empty() {
+                let val = self.validate(loader.dataset(), validation, config.batch_size);
+                pb.set_message(format!("val_pi_loss: {:.2e} val_v_loss: {:.2e} val_policy_acc: {:.3} val_value_acc: {:.3}",
+                                       val.pi_loss, val.v_loss, val.policy_accuracy, val.value_accuracy));
+                epoch_values.extend([
+                    ("val_pi_loss", val.pi_loss as f64),
+                    ("val_v_loss", val.v_loss as f64),
+                    ("val_policy_accuracy", val.policy_accuracy as f64),
+                    ("val_value_accuracy", val.value_accuracy as f64),
+                ]);
+                let improved = match &best {
+                    Some((best_loss, _)) => val.loss() < *best_loss,
+                    None => true,
+                };
+                if improved {
+                    best = Some((val.loss(), self.snapshot_variables()));
+                    epochs_without_improvement = 0;
+                } else {
+                    epochs_without_improvement += 1;
+                }
+            }
+            metrics.log_epoch(&epoch_values);
+            optimizer.end_epoch();
             pb.inc(1);
+            if config.early_stopping_patience.is_some_and(|patience| epochs_without_improvement >= patience) {
+                println!("Early stopping after {} epochs", epoch + 1);
+                break;
+            }
         }
         pb.finish();
+        if let Some((best_loss, variables)) = best {
+            println!("Restoring weights of the best epoch (val loss {:.4})", best_loss);
+            self.restore_variables(&variables);
+        }
+        metrics.flush();
+    }
+
+    // Losses and accuracies on held out samples, in evaluation mode
+    pub fn validate(&self, dataset: &impl Dataset, ids: &[usize], batch_size: usize) -> ValidationStats {
+        let mut pi_losses = AverageMeter::default();
+        let mut v_losses = AverageMeter::default();
+        let mut policy_accuracies = AverageMeter::default();
+        let mut value_accuracies = AverageMeter::default();
+        no_grad(|| {
+            for batch in ids.chunks(batch_size) {
+                let Batch { boards, pis: target_pis, values: target_vs, .. } = Batch::from_dataset(dataset, batch).to_device(get_base_device());
+                let (out_pi, out_v) = self.nnet.forward(&boards, false);
+                pi_losses.update(self.loss_pi(&target_pis, &out_pi).double_value(&[]) as f32, batch.len());
+                v_losses.update(self.loss_v(&target_vs, &out_v).double_value(&[]) as f32, batch.len());
+                let same_move = out_pi.argmax(1, false).eq_tensor(&target_pis.argmax(1, false));
+                policy_accuracies.update(same_move.to_kind(Kind::Float).mean(Kind::Float).double_value(&[]) as f32, batch.len());
+                // drawn positions have no sign to predict
+                let decided = target_vs.ne(0.0);
+                let decided_count = decided.sum(Kind::Int64).int64_value(&[]) as usize;
+                if decided_count > 0 {
+                    let same_sign = out_v.view(-1).sign().eq_tensor(&target_vs.sign()).logical_and(&decided);
+                    let correct = same_sign.sum(Kind::Float).double_value(&[]) as f32;
+                    value_accuracies.update(correct / decided_count as f32, decided_count);
+                }
+            }
+        });
+        ValidationStats {
+            pi_loss: pi_losses.avg(),
+            v_loss: v_losses.avg(),
+            policy_accuracy: policy_accuracies.avg(),
+            value_accuracy: value_accuracies.avg(),
+        }
+    }
+
+    // Name, weight and loss of each auxiliary head, averaged over the samples that have auxiliary targets
+    fn aux_losses(&self, batch: &Batch, out_aux: &AuxOutputs) -> Vec<(&'static str, f64, Tensor)> {
+        let mut losses = vec![];
+        if !self.aux.is_enabled() {
+            return losses;
+        }
+        let count = batch.aux_mask.sum(Kind::Float).clamp_min(1.0);
+        let scalar_loss = |out: &Tensor, column: i64, scale: f32| {
+            let targets = batch.aux.select(1, column) / scale as f64;
+            ((out.view(-1) - targets).square() * &batch.aux_mask).sum(Kind::Float) / &count
+        };
+        if let Some(out) = &out_aux.turns {
+            losses.push(("aux_turns_loss", self.aux.turns_weight, scalar_loss(out, 0, AUX_TURNS_SCALE)));
+        }
+        if let Some(out) = &out_aux.length {
+            losses.push(("aux_length_loss", self.aux.length_weight, scalar_loss(out, 1, AUX_LENGTH_SCALE)));
+        }
+        if let Some(out) = &out_aux.opponent_pi {
+            let targets = batch.aux.narrow(1, 2, 4);
+            let loss = -(targets * out * batch.aux_mask.unsqueeze(1)).sum(Kind::Float) / &count;
+            losses.push(("aux_opponent_pi_loss", self.aux.opponent_policy_weight, loss));
+        }
+        losses
     }
 
+    // Deep copy of all variables, batch norm statistics included
+    fn snapshot_variables(&self) -> HashMap<String, Tensor> {
+        no_grad(|| self.vs.variables().into_iter().map(|(name, var)| (name, var.copy())).collect())
+    }
+
+    fn restore_variables(&mut self, variables: &HashMap<String, Tensor>) {
+        no_grad(|| {
+            for (name, mut var) in self.vs.variables() {
+                if let Some(saved) = variables.get(&name) {
+                    var.copy_(saved);
+                }
+            }
+        });
+    }
+
+    // Mean entropy of the predicted policies, out_pi holding log probabilities
+    fn policy_entropy(&self, out_pi: &Tensor) -> Tensor {
+        -(out_pi.exp() * out_pi).sum_dim_intlist(1, false, tch::Kind::Float).mean(tch::Kind::Float)
+    }
+
+    // L2 norm of all gradients, to call between backward and step, read back from the device once
+    fn grad_norm(&self) -> f64 {
+        no_grad(|| {
+            let squared_norms = self.vs.trainable_variables().iter()
+                .map(|var| var.grad())
+                .filter(|grad| grad.defined())
+                .map(|grad| grad.pow_tensor_scalar(2).sum(Kind::Float))
+                .collect::<Vec<Tensor>>();
+            if squared_norms.is_empty() {
+                return 0.0;
+            }
+            Tensor::stack(&squared_norms, 0).sum(Kind::Float).sqrt().double_value(&[])
+        })
+    }
+
+
+    // Predicted values of the samples, in evaluation mode
+    pub fn predict_values(&self, dataset: &impl Dataset, ids: &[usize], batch_size: usize) -> Vec<f32> {
+        no_grad(|| {
+            ids.chunks(batch_size)
+                .flat_map(|batch| {
+                    let batch = Batch::from_dataset(dataset, batch).to_device(get_base_device());
+                    let (_, v) = self.nnet.forward(&batch.boards, false);
+                    Vec::<f32>::try_from(v.view(-1).to_device(Device::Cpu)).unwrap()
+                })
+                .collect()
+        })
+    }
+
+    pub fn predict<B: GameBoard>(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32) {
+        let (actions, value, _) = self.predict_with_opponent(board);
+        (actions, value)
+    }
+
+    // Policies and values of many boards in a single forward pass
+    pub fn predict_batch<B: GameBoard>(&self, boards: &[CanonicalBoard<B>]) -> Vec<([f32; 4], f32)> {
+        if boards.is_empty() {
+            return vec![];
+        }
+        let input = Tensor::stack(&boards.iter().map(|board| board.to_tensor()).collect::<Vec<Tensor>>(), 0).to_device(get_base_device());
+        let (pi, v) = no_grad(|| self.nnet.forward(&input, false));
+        let pis: Vec<f32> = pi.exp().view(-1).to_device(Device::Cpu).try_into().unwrap();
+        let values: Vec<f32> = v.view(-1).to_device(Device::Cpu).try_into().unwrap();
+        pis.chunks_exact(4).zip(values).map(|(pi, value)| ([pi[0], pi[1], pi[2], pi[3]], value)).collect()
+    }
 
-    pub fn predict(&self, board: &CanonicalBoard) -> ([f32; 4], f32) {
+    pub fn has_opponent_policy(&self) -> bool {
+        self.aux.opponent_policy_weight > 0.0
+    }
+
+    // Policy and value along with the opponent's policy when the model has the opponent policy head
+    pub fn predict_with_opponent<B: GameBoard>(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32, Option<[f32; 4]>) {
         let device = get_base_device();
         let  tensor_board = if device.is_cuda() {
             board.to_tensor().contiguous().to_device(device)
         }else{
             board.to_tensor()
         };
-        let (pi, v) = no_grad(|| {
-            self.nnet.forward(&tensor_board, false)
-        });
-        let pi: Vec<f32> = pi.exp().view(-1).try_into().unwrap();
-        let value: f32 = v.try_into().unwrap();
-        let mut actions: [f32; 4] = [0.0; 4];
-        actions.copy_from_slice(&pi);
-        (actions, value)
+        let to_actions = |pi: Tensor| {
+            let pi: Vec<f32> = pi.exp().view(-1).to_device(Device::Cpu).try_into().unwrap();
+            let mut actions: [f32; 4] = [0.0; 4];
+            actions.copy_from_slice(&pi);
+            actions
+        };
+        no_grad(|| {
+            if self.has_opponent_policy() {
+                let (pi, v, aux) = self.nnet.forward_aux(&tensor_board, false);
+                (to_actions(pi), v.try_into().unwrap(), aux.opponent_pi.map(to_actions))
+            } else {
+                let (pi, v) = self.nnet.forward(&tensor_board, false);
+                (to_actions(pi), v.try_into().unwrap(), None)
+            }
+        })
     }
 
 
@@ -138,6 +425,15 @@ impl AlphaZeroModel {
     }
 
     pub fn load_checkpoint(&mut self, model_path: &PathBuf) -> Result<(), std::io::Error> {
+        // checkpoints saved before the auxiliary heads were enabled leave them freshly initialized
+        if self.aux.is_enabled() {
+            match self.vs.load_partial(model_path) {
+                Ok(missing) if !missing.is_empty() => println!("Variables not in the checkpoint: {:?}", missing),
+                Ok(_) => {}
+                Err(e) => println!("Failed to load checkpoint: {}", e),
+            }
+            return Ok(());
+        }
         self.vs.load(model_path).unwrap_or_else(|e| {
             println!("Failed to load checkpoint: {}", e);
         });
@@ -145,22 +441,58 @@ impl AlphaZeroModel {
     }
 }
 
-impl Clone for AlphaZeroModel {
-    fn clone(&self) -> Self {
-        let mut vs = get_base_var_store();
-        vs.copy(&self.vs).unwrap();
-        vs.trainable_variables();
-        let nnet = NeuralNetwork::new(&vs.root(), self.num_channels);
-        Self {
-            vs,
-            nnet,
-            num_channels: self.num_channels,
-        }
+impl Default for AlphaZeroModel {
+    fn default() -> Self {
+        Self::new(NUM_CHANNELS, BOARD_SIZE)
     }
 }
 
-impl Default for AlphaZeroModel {
-    fn default() -> Self {
-        Self::new(NUM_CHANNELS)
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::game::{CanCanonical, Sample, test_board};
+    use crate::optimizer::OptimizerConfig;
+    use crate::terminal::TerminalConfig;
+    use crate::utils::test_dir;
+
+    // One epoch on a board whose targets are Up and a win
+    fn train(model: &mut AlphaZeroModel, board: &CanonicalBoard<impl GameBoard>, metrics: &mut MetricsLogger) {
+        let samples: Vec<Sample> = board.get_mirroring_and_rotation(&[1.0, 0.0, 0.0, 0.0]).into_iter()
+            .map(|(board, pi, _)| (board, pi, 1.0))
+            .collect();
+        let loader = DataLoader::new(Arc::new(samples), (0..8).collect(), 4, 0);
+        let mut optimizer = Optimizer::new(model, OptimizerConfig::new(0.01));
+        let config = TrainConfig { epochs: 1, batch_size: 4, early_stopping_patience: None, prefetch_batches: 0 };
+        model.train(&loader, &[], &mut optimizer, config, metrics, &mut StdRng::seed_from_u64(1));
+    }
+
+    fn assert_close((pi, value): ([f32; 4], f32), (expected_pi, expected_value): ([f32; 4], f32)) {
+        assert!(pi.iter().zip(expected_pi).all(|(p, e)| (p - e).abs() < 1e-6), "{:?} != {:?}", pi, expected_pi);
+        assert!((value - expected_value).abs() < 1e-6, "{} != {}", value, expected_value);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn snapshot_keeps_the_weights_it_was_taken_with() {
+        let board = test_board(r#"[{"x":3,"y":3},{"x":3,"y":2},{"x":3,"y":1}]"#, 90, r#"[{"x":6,"y":0},{"x":5,"y":0},{"x":4,"y":0}]"#, 80, "[]")
+            .as_canonical(1, TerminalConfig::default());
+        let mut metrics = MetricsLogger::new(&test_dir("snapshot"), false).unwrap();
+        let mut model = AlphaZeroModel::new(8, 7);
+        train(&mut model, &board, &mut metrics);
+
+        let snapshot = model.snapshot();
+        let prediction = snapshot.predict(&board);
+        assert_close(prediction, model.predict(&board));
+
+        train(&mut model, &board, &mut metrics);
+        assert_close(snapshot.predict(&board), prediction);
+        let (pi, _) = model.predict(&board);
+        assert!(pi.iter().zip(prediction.0).any(|(p, q)| (p - q).abs() > 1e-6));
+    }
+}
+
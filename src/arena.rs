@@ -2,35 +2,42 @@ use battlesnake_game_types::types::{Move, SnakeId};
 use indicatif::ProgressStyle;
 use itertools::Itertools;
 
-use crate::game::{Board, BoardInit, CanCanonical};
+use crate::alpha_zero_model::SharedModel;
+use crate::evaluator::Evaluator;
+use crate::game::{BoardInit, CanCanonical, GameBoard};
 use crate::mcts::MCTS;
-use crate::normal_mcts::{ mcts_parallel, MCTSNode};
+use crate::normal_mcts::mcts_parallel;
+use crate::rollout::RolloutConfig;
+use crate::outcome::{GameOutcome, OutcomeStats};
+use crate::terminal::TerminalConfig;
 
-pub struct Arena {
-    n_player: MCTS,
-    p_player: Option<MCTS>,
-    min_health_threshold:u8
+pub struct Arena<B: GameBoard, N: Evaluator<B> = SharedModel, P: Evaluator<B> = SharedModel> {
+    n_player: MCTS<B, N>,
+    p_player: Option<MCTS<B, P>>,
+    terminal: TerminalConfig,
+    // outcomes of the last play_games call, seen from n_player
+    pub stats: OutcomeStats,
 }
 
-impl Arena {
-    pub fn new(n_player: MCTS, p_player: Option<MCTS>, health_kill_threshold:u8) -> Arena {
+impl<B: GameBoard, N: Evaluator<B> + Clone, P: Evaluator<B> + Clone> Arena<B, N, P> {
+    pub fn new(n_player: MCTS<B, N>, p_player: Option<MCTS<B, P>>, terminal: TerminalConfig) -> Arena<B, N, P> {
         Arena {
             n_player,
             p_player,
-            min_health_threshold: health_kill_threshold,
+            terminal,
+            stats: OutcomeStats::new(),
         }
     }
 
 
-    pub fn play_game(&mut self) -> f32 {
+    pub fn play_game(&mut self) -> Option<GameOutcome> {
         if let Some(ref mut p_player) = &mut self.p_player{
-            let board = Board::init_random_board();
+            let board = B::init_random_board();
             let mut current_player = 1;
-            let mut canonical_board = board.as_canonical(current_player, self.min_health_threshold);
+            let mut canonical_board = board.as_canonical(current_player, self.terminal);
             loop {
-                let value = canonical_board.get_game_ended(1);
-                if value != 0.0 {
-                    return value;
+                if let Some(outcome) = canonical_board.get_game_outcome(1) {
+                    return Some(outcome);
                 }
                 let actions = if current_player == 1 {
                     self.n_player.get_action_prob(&canonical_board, 0.0)
@@ -45,8 +52,7 @@ impl Arena {
                 (canonical_board, current_player) = canonical_board.get_next_state(best_action_index, false);
             }
         }
-        0.0
-
+        None
     }
 
     pub fn play_games(&mut self, num: i32) -> (i32, i32, i32) {
@@ -59,24 +65,25 @@ impl Arena {
             .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg} ({eta})")
             .unwrap()
             .progress_chars("#>-"));
+        self.stats = OutcomeStats::new();
         for _ in 0..num {
-            let game_result = self.play_game();
-            if game_result == 1.0 {
-                n_wins += 1;
-            } else if game_result == -1.0 {
-                p_wins += 1;
-            } else {
-                draws += 1;
+            let Some(outcome) = self.play_game() else { break };
+            self.stats.record(&outcome);
+            match outcome {
+                GameOutcome::Win { .. } => n_wins += 1,
+                GameOutcome::Loss { .. } => p_wins += 1,
+                GameOutcome::Draw { .. } => draws += 1,
             }
             pb.inc(1);
             pb.set_message(format!("New wins: {} Past wins: {} Draws: {}", n_wins, p_wins, draws));
         }
         pb.finish();
+        println!("ARENA {}", self.stats);
         (n_wins, p_wins, draws)
     }
 
 
-    pub fn play_games_vs_normal_mcts(&mut self, num: i32, num_mcts_iterations:usize) -> (i32, i32, i32) {
+    pub fn play_games_vs_normal_mcts(&mut self, num: i32, num_mcts_iterations:usize, rollout: RolloutConfig) -> (i32, i32, i32) {
         let mut n_wins = 0;
         let mut p_wins = 0;
         let mut draws = 0;
@@ -86,47 +93,41 @@ impl Arena {
             .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg} ({eta})")
             .unwrap()
             .progress_chars("#>-"));
+        self.stats = OutcomeStats::new();
         for _ in 0..num {
-            let game_result = self.play_game_vs_normal_mcts(num_mcts_iterations);
-            if game_result == 1.0 {
-                n_wins += 1;
-            } else if game_result == -1.0 {
-                p_wins += 1;
-            } else {
-                draws += 1;
+            let outcome = self.play_game_vs_normal_mcts(num_mcts_iterations, &rollout);
+            self.stats.record(&outcome);
+            match outcome {
+                GameOutcome::Win { .. } => n_wins += 1,
+                GameOutcome::Loss { .. } => p_wins += 1,
+                GameOutcome::Draw { .. } => draws += 1,
             }
             pb.inc(1);
             pb.set_message(format!("New wins: {} Past wins: {} Draws: {}", n_wins, p_wins, draws));
         }
         pb.finish();
+        println!("ARENA {}", self.stats);
         (n_wins, p_wins, draws)
     }
 
 
-    pub fn play_game_vs_normal_mcts(&mut self, num_mcts_iterations:usize) -> f32 {
-        let board = Board::init_random_board();
+    pub fn play_game_vs_normal_mcts(&mut self, num_mcts_iterations:usize, rollout: &RolloutConfig) -> GameOutcome {
+        let board = B::init_random_board();
         let mut current_player = 1;
-        let mut canonical_board = board.as_canonical(current_player, self.min_health_threshold);
+        let mut canonical_board = board.as_canonical(current_player, self.terminal);
         println!("{}",canonical_board.board);
 
         let mut iter = 0;
         let mut temp_moves = vec![];
         loop {
-            let value = canonical_board.get_game_ended(1);
-            if value != 0.0 {
-                return value;
+            if let Some(outcome) = canonical_board.get_game_outcome(1) {
+                return outcome;
             }
             let actions = if current_player == 1 {
                 self.n_player.get_action_prob(&canonical_board, 0.0)
             } else {
                 let other_snake_id= SnakeId(1);
-                let best_node = mcts_parallel(
-                        MCTSNode::new(canonical_board.board, None, None, 0),
-                        &other_snake_id,
-                        num_mcts_iterations,
-                    12
-                    );
-                let best_move  = best_node.get_best_move_made(&other_snake_id);
+                let best_move = mcts_parallel(canonical_board.board, &other_snake_id, num_mcts_iterations, 12, rollout);
                 let mut actions = [0.0; 4];
                 actions[best_move.as_index()] = 1.0;
                 actions
@@ -0,0 +1,187 @@
+use indicatif::ProgressStyle;
+
+use crate::Args;
+use crate::canonical_board::CanonicalBoard;
+use crate::game::{BoardInit, CanCanonical, GameBoard, Sample};
+use crate::normal_mcts::{mcts_parallel_root, root_policy_and_win_rate};
+use crate::rollout::RolloutConfig;
+use crate::terminal::TerminalConfig;
+use crate::utils::choose_index_based_on_probability;
+
+// Warm start of the AlphaZero loop: examples labelled by the rollout MCTS of normal_mcts instead of the network.
+// The policy target is the root visit distribution of the snake to move and the value target its rollout win rate
+// mapped to [-1, 1]
+#[derive(Clone, Copy, Debug)]
+pub struct BootstrapConfig {
+    pub games: usize,
+    pub mcts_iterations: usize,
+    pub num_threads: usize,
+    // moves are sampled from the visit distribution for this many half turns, then the most visited is played
+    pub temp_threshold: i32,
+    pub rollout: RolloutConfig,
+}
+
+impl BootstrapConfig {
+    pub fn from_args(args: &Args, games: usize) -> Self {
+        BootstrapConfig {
+            games,
+            mcts_iterations: args.bootstrap_mcts_iterations,
+            num_threads: args.bootstrap_threads,
+            temp_threshold: args.temp_threshold,
+            rollout: RolloutConfig::from_args(args),
+        }
+    }
+}
+
+pub fn generate_bootstrap_examples<B: GameBoard>(config: BootstrapConfig, terminal: TerminalConfig) -> Vec<Sample> {
+    let pb = indicatif::ProgressBar::new(config.games as u64);
+    pb.set_style(ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>7}/{len:7} ({eta})")
+        .unwrap()
+        .progress_chars("#>-"));
+    let mut samples = vec![];
+    for _ in 0..config.games {
+        samples.extend(bootstrap_episode::<B>(config, terminal));
+        pb.inc(1);
+    }
+    pb.finish();
+    samples
+}
+
+fn bootstrap_episode<B: GameBoard>(config: BootstrapConfig, terminal: TerminalConfig) -> Vec<Sample> {
+    let board = B::init_random_board();
+    let mut canonical_board = board.as_canonical(1, terminal);
+    let mut samples = vec![];
+    let mut episode_step = 0;
+    while canonical_board.get_game_outcome(1).is_none() {
+        episode_step += 1;
+        let (position_samples, pi) = label_position(&canonical_board, &config);
+        samples.extend(position_samples);
+        let action = choose_action(&pi, &canonical_board.get_valid_moves(), episode_step < config.temp_threshold);
+        (canonical_board, _) = canonical_board.get_next_state(action, false);
+    }
+    samples
+}
+
+// Symmetries of the position labelled by the search of the snake to move, and the policy they were labelled with
+fn label_position<B: GameBoard>(canonical_board: &CanonicalBoard<B>, config: &BootstrapConfig) -> (Vec<Sample>, [f32; 4]) {
+    // the board only moves once both snakes chose, so the snake to move searches the current board
+    let snake_id = canonical_board.get_current_snake();
+    let stats = mcts_parallel_root(canonical_board.board, config.mcts_iterations, config.num_threads, &config.rollout);
+    let (pi, win_rate) = root_policy_and_win_rate(&stats, &snake_id);
+    let pi = valid_policy(pi, &canonical_board.get_valid_moves());
+    let samples = canonical_board.get_mirroring_and_rotation(&pi).into_iter()
+        .map(|(board, pi, _)| (board, pi, 2.0 * win_rate - 1.0))
+        .collect();
+    (samples, pi)
+}
+
+// The visit distribution restricted to the valid moves, uniform over them when none of them was visited
+fn valid_policy(pi: [f32; 4], valid_moves: &[bool; 4]) -> [f32; 4] {
+    let mut pi = pi;
+    for (p, valid) in pi.iter_mut().zip(valid_moves) {
+        if !valid {
+            *p = 0.0;
+        }
+    }
+    let sum = pi.iter().sum::<f32>();
+    if sum == 0.0 {
+        let valid_count = valid_moves.iter().filter(|valid| **valid).count().max(1) as f32;
+        return valid_moves.map(|valid| if valid { 1.0 / valid_count } else { 0.0 });
+    }
+    pi.map(|p| p / sum)
+}
+
+// Sampled from pi while sample, else the most likely move, replaced by the first valid move when it is not valid
+fn choose_action(pi: &[f32; 4], valid_moves: &[bool; 4], sample: bool) -> usize {
+    let action = if sample {
+        choose_index_based_on_probability(pi)
+    } else {
+        pi.iter().enumerate().max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap()).unwrap().0
+    };
+    if valid_moves[action] {
+        action
+    } else {
+        valid_moves.iter().position(|valid| *valid).unwrap_or(0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use battlesnake_game_types::types::SnakeId;
+
+    use super::*;
+    use crate::config::NUM_SYMMETRIES;
+    use crate::game::{Board7x7, test_board};
+
+    fn config() -> BootstrapConfig {
+        BootstrapConfig {
+            games: 1,
+            mcts_iterations: 100,
+            num_threads: 2,
+            temp_threshold: 10,
+            rollout: RolloutConfig::default(),
+        }
+    }
+
+    fn check_samples(samples: &[Sample], valid_moves: &[bool; 4]) {
+        assert_eq!(samples.len(), NUM_SYMMETRIES);
+        let value = samples[0].2;
+        assert!((-1.0..=1.0).contains(&value));
+        assert!(samples.iter().all(|(_, pi, sample_value)| (pi.iter().sum::<f32>() - 1.0).abs() < 1e-5 && *sample_value == value));
+        // the first symmetry is the position itself
+        let pi = samples[0].1;
+        assert!(pi.iter().zip(valid_moves).all(|(p, valid)| *valid || *p == 0.0));
+    }
+
+    #[test]
+    fn value_is_from_the_perspective_of_the_snake_to_move() {
+        // snake 0 starves on its next move whatever it plays, far from snake 1
+        let board = test_board(
+            r#"[{"x":3,"y":3},{"x":3,"y":2},{"x":3,"y":1}]"#, 1,
+            r#"[{"x":6,"y":6},{"x":5,"y":6},{"x":4,"y":6}]"#, 100,
+            "[]",
+        );
+        let canonical_board = board.as_canonical(1, TerminalConfig::default());
+        assert_eq!(canonical_board.get_current_snake(), SnakeId(0));
+        let (samples, pi) = label_position(&canonical_board, &config());
+        check_samples(&samples, &canonical_board.get_valid_moves());
+        assert_eq!(samples[0].1, pi);
+        assert_eq!(samples[0].2, -1.0);
+
+        let (canonical_board, _) = canonical_board.get_next_state(choose_action(&pi, &canonical_board.get_valid_moves(), false), false);
+        assert_eq!(canonical_board.get_current_snake(), SnakeId(1));
+        let (samples, _) = label_position(&canonical_board, &config());
+        check_samples(&samples, &canonical_board.get_valid_moves());
+        assert_eq!(samples[0].2, 1.0);
+    }
+
+    #[test]
+    fn episode_samples_are_normalized_symmetries() {
+        let samples = bootstrap_episode::<Board7x7>(config(), TerminalConfig::default());
+        assert!(!samples.is_empty());
+        for position in samples.chunks(NUM_SYMMETRIES) {
+            check_samples(position, &[true; 4]);
+        }
+    }
+
+    #[test]
+    fn policy_keeps_only_valid_moves() {
+        assert_eq!(valid_policy([0.5, 0.25, 0.25, 0.0], &[true, false, true, true]), [2.0 / 3.0, 0.0, 1.0 / 3.0, 0.0]);
+        // nothing valid was visited
+        assert_eq!(valid_policy([0.0, 1.0, 0.0, 0.0], &[true, false, false, true]), [0.5, 0.0, 0.0, 0.5]);
+        assert_eq!(valid_policy([0.0; 4], &[false, true, true, true]), [0.0, 1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0]);
+    }
+
+    #[test]
+    fn invalid_picks_fall_back_to_the_first_valid_move() {
+        let pi = [0.0, 1.0, 0.0, 0.0];
+        assert_eq!(choose_action(&pi, &[true, true, false, false], true), 1);
+        assert_eq!(choose_action(&pi, &[true, true, false, false], false), 1);
+        assert_eq!(choose_action(&pi, &[false, false, true, true], true), 2);
+        assert_eq!(choose_action(&pi, &[false, false, true, true], false), 2);
+        assert_eq!(choose_action(&pi, &[false; 4], true), 0);
+    }
+}
+
@@ -1,21 +1,29 @@
-use battlesnake_game_types::types::{FoodGettableGame, HeadGettableGame, HealthGettableGame, Move, ReasonableMovesGame, SnakeBodyGettableGame, SnakeId, VictorDeterminableGame};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+use battlesnake_game_types::types::{Move, SnakeId};
 use battlesnake_game_types::wire_representation::Position;
 use itertools::Itertools;
 use ndarray::Array2;
 use tch::Tensor;
 
-use crate::config::BOARD_SIZE;
 use crate::game;
-use crate::game::{Board, MoveBattleSnake};
+use crate::game::{ArrayBoard, GameBoard, MoveBattleSnake, Sample};
+use crate::outcome::{EndCause, GameOutcome, get_death_causes};
+use crate::terminal::TerminalConfig;
 
 pub type AllBoardInfo = (Option<Position>, Option<Vec<Position>>, Option<Position>, Option<Vec<Position>>, Vec<Position>);
 
-pub const  Y_MAPPING:[usize;11] = [0, BOARD_SIZE as usize, (2*BOARD_SIZE) as usize, (3*BOARD_SIZE) as usize, (4*BOARD_SIZE) as usize, (5*BOARD_SIZE) as usize, (6*BOARD_SIZE) as usize, (7*BOARD_SIZE) as usize, (8*BOARD_SIZE) as usize, (9*BOARD_SIZE) as usize, (10*BOARD_SIZE) as usize];
+// 128 bit hash of the row-major cell codes (y * size + x) used as MCTS hashmap key, copied without allocating
+pub type BoardKey = u128;
+
+// Cells of the largest supported board
+const MAX_CELLS: usize = 19 * 19;
 
 
-pub fn rotate_board(board: &[[f32; 11]; 11], rotation: usize) -> [[f32; 11]; 11] {
-    let mut new_board = [[0.0; 11]; 11];
+pub fn rotate_board(board: &ArrayBoard, rotation: usize) -> ArrayBoard {
     let n = board.len(); // Assuming the board is always square
+    let mut new_board = vec![vec![0.0; n]; n];
 
     for i in 0..n {
         for j in 0..n {
@@ -31,9 +39,9 @@ pub fn rotate_board(board: &[[f32; 11]; 11], rotation: usize) -> [[f32; 11]; 11]
     new_board
 }
 
-pub fn flip_board_horizontal(board: &[[f32; 11]; 11]) -> [[f32; 11]; 11] {
-    let mut new_board = [[0.0; 11]; 11];
+pub fn flip_board_horizontal(board: &ArrayBoard) -> ArrayBoard {
     let n = board.len(); // Assuming the board is square
+    let mut new_board = vec![vec![0.0; n]; n];
 
     for i in 0..n {
         for j in 0..n {
@@ -44,6 +52,33 @@ pub fn flip_board_horizontal(board: &[[f32; 11]; 11]) -> [[f32; 11]; 11] {
 }
 
 
+// The NUM_SYMMETRIES rotations and mirrors of a sample, in the order they are stored in examples files
+pub fn get_symmetries(array_board: &ArrayBoard, pi: &[f32; 4], value: f32) -> Vec<Sample> {
+    let mut symmetries: Vec<Sample> = Vec::new();
+
+    let rotations = [0, 1, 2, 3]; // Represents 0, 90, 180, and 270 degrees
+    let flips_horizontal = [false, true]; // Represents no flip and horizontal flip
+
+    for &rotation in &rotations {
+        for &flip_horizontal in &flips_horizontal {
+            let mut new_board = rotate_board(array_board, rotation);
+            if flip_horizontal {
+                new_board = flip_board_horizontal(&new_board);
+            }
+            let new_pi = rotate_policy(pi, rotation, flip_horizontal);
+            symmetries.push((new_board, new_pi, value));
+        }
+    }
+    symmetries
+}
+
+
+// The policy of every symmetry of get_symmetries, in the same order
+pub fn get_policy_symmetries(pi: &[f32; 4]) -> Vec<[f32; 4]> {
+    (0..4).flat_map(|rotation| [false, true].map(|flip_horizontal| rotate_policy(pi, rotation, flip_horizontal))).collect()
+}
+
+
 pub fn rotate_policy(pi: &[f32; 4], rotation: usize, flip_horizontal: bool) -> [f32; 4] {
     // Adjust the policy vector based on rotation and flip
     // You need to map the directions accordingly
@@ -67,26 +102,31 @@ pub fn rotate_policy(pi: &[f32; 4], rotation: usize, flip_horizontal: bool) -> [
 
 
 #[derive(Clone, Debug, Copy)]
-pub struct CanonicalBoard {
-    pub board: Board,
+pub struct CanonicalBoard<B: GameBoard> {
+    pub board: B,
     pub first_player: i32,
     pub prev_action: Option<Move>,
-    pub min_health_threshold: u8,
+    pub terminal: TerminalConfig,
+    pub turn: u32,
+    // why each snake died on the last simulated turn
+    pub death_causes: [Option<EndCause>; 2],
 }
 
-impl CanonicalBoard {
-    pub fn new(board: Board, first_player: i32, prev_action: Option<Move>, health_kill_threshold: u8) -> Self {
+impl<B: GameBoard> CanonicalBoard<B> {
+    pub fn new(board: B, first_player: i32, prev_action: Option<Move>, terminal: TerminalConfig) -> Self {
         CanonicalBoard {
             board,
             first_player,
             prev_action,
-            min_health_threshold: health_kill_threshold,
+            terminal,
+            turn: 0,
+            death_causes: [None, None],
         }
     }
 
 
     pub fn to_tensor(&self) -> Tensor {
-        let (board_size_x, board_size_y) = (11, 11); // Assuming a fixed board size, adjust if necessary
+        let (board_size_x, board_size_y) = (B::SIZE, B::SIZE);
         let mut array_board: Array2<f32> = Array2::zeros((board_size_x, board_size_y));
         let (self_head, self_body, other_head, other_body, foods) = self.get_info_for_repr();
 
@@ -114,7 +154,17 @@ impl CanonicalBoard {
     }
 
 
-    pub fn reset_and_clone_as_current_player(&self) -> CanonicalBoard {
+    // Same board with the opponent to move first, as seen by the opponent
+    pub fn as_opponent(&self) -> CanonicalBoard<B> {
+        CanonicalBoard {
+            first_player: -self.get_current_player(),
+            prev_action: None,
+            ..*self
+        }
+    }
+
+
+    pub fn reset_and_clone_as_current_player(&self) -> CanonicalBoard<B> {
         let mut new_board = *self;
         if new_board.prev_action.is_some() {
             new_board.prev_action = None;
@@ -124,31 +174,14 @@ impl CanonicalBoard {
     }
 
 
-    pub fn get_mirroring_and_rotation(&self, pi: &[f32; 4]) -> Vec<([[f32; 11]; 11], [f32; 4], f32)> {
-        let mut symmetries: Vec<([[f32; 11]; 11], [f32; 4], f32)> = Vec::new();
-
-        let rotations = [0, 1, 2, 3]; // Represents 0, 90, 180, and 270 degrees
-        let flips_horizontal = [false, true]; // Represents no flip and horizontal flip
-        let current_player = self.get_current_player();
-
-        let array_board = self.to_array_board();
-        for &rotation in &rotations {
-            for &flip_horizontal in &flips_horizontal {
-                let mut new_board = rotate_board(&array_board, rotation);
-                if flip_horizontal {
-                    new_board = flip_board_horizontal(&new_board);
-                }
-                let new_pi = rotate_policy(pi, rotation, flip_horizontal);
-                symmetries.push((new_board, new_pi, current_player as f32));
-            }
-        }
-        symmetries
+    pub fn get_mirroring_and_rotation(&self, pi: &[f32; 4]) -> Vec<Sample> {
+        get_symmetries(&self.to_array_board(), pi, self.get_current_player() as f32)
     }
 
 
-    pub fn to_array_board(&self) -> [[f32; 11]; 11] {
-        let mut result = [[0.0; 11]; 11];
-        let board_size_i32 = (BOARD_SIZE - 1) as i32;
+    pub fn to_array_board(&self) -> ArrayBoard {
+        let mut result = vec![vec![0.0; B::SIZE]; B::SIZE];
+        let board_size_i32 = (B::SIZE - 1) as i32;
         let (self_head, self_body, other_head, other_body, foods) = self.get_info_for_repr();
         if let Some(self_head) = self_head {
             result[(board_size_i32 - self_head.y) as usize][self_head.x as usize] = 1.0;
@@ -179,42 +212,48 @@ impl CanonicalBoard {
     }
 
 
-    pub fn to_hashmap_bytes(&self) -> [u8; 121] {
-        let mut result = [0u8; 121];
+    pub fn to_hashmap_key(&self) -> BoardKey {
+        let mut cells = [0u8; MAX_CELLS];
+        let result = &mut cells[..B::SIZE * B::SIZE];
         let (self_head, self_body, other_head, other_body, foods) = self.get_info_for_repr();
 
         if let Some(self_head) = self_head {
-            result[Y_MAPPING[self_head.y as usize] + self_head.x as usize] = 1;
+            result[self_head.y as usize * B::SIZE + self_head.x as usize] = 1;
             if let Some(self_body) = self_body {
                 for body in self_body {
                     if body != self_head {
-                        result[Y_MAPPING[body.y as usize] + body.x as usize] = 2;
+                        result[body.y as usize * B::SIZE + body.x as usize] = 2;
                     }
                 }
             }
         }
 
         if let Some(other_head) = other_head {
-            result[Y_MAPPING[other_head.y as usize] + other_head.x as usize] = 3;
+            result[other_head.y as usize * B::SIZE + other_head.x as usize] = 3;
             if let Some(other_body) = other_body {
                 for body in other_body {
                     if body != other_head {
-                        result[Y_MAPPING[body.y as usize] + body.x as usize] = 4;
+                        result[body.y as usize * B::SIZE + body.x as usize] = 4;
                     }
                 }
             }
         }
 
         for food in foods {
-            result[Y_MAPPING[food.y as usize] + food.x as usize] = 5;
+            result[food.y as usize * B::SIZE + food.x as usize] = 5;
         }
-        result
+        // two independent 64 bit hashes, the second one salted
+        let (mut low, mut high) = (DefaultHasher::new(), DefaultHasher::new());
+        high.write_u8(1);
+        low.write(result);
+        high.write(result);
+        ((high.finish() as u128) << 64) | low.finish() as u128
     }
 
 
     pub fn to_hashmap_string(&self) -> String {
         let (self_head, self_body, other_head, other_body, foods) = self.get_info_for_repr();
-        let board_size = BOARD_SIZE as usize;
+        let board_size = B::SIZE;
         let mut array_string = vec!['a'; board_size * board_size];  // Pre-fill the string with '0'
 
         // Inline function to reduce code duplication
@@ -289,40 +328,72 @@ impl CanonicalBoard {
     }
 
 
-    pub fn get_game_ended(&self, player_id: i32) -> f32 {
+    pub fn get_game_outcome(&self, player_id: i32) -> Option<GameOutcome> {
+        let player_snake = game::player_to_snake(player_id);
+        let opponent_snake = game::player_to_snake(-player_id);
+        let mut causes = self.death_causes;
         let mut is_over = self.board.is_over();
         let mut winner = self.board.get_winner();
         if !is_over {
-            let snake_0_is_dead = self.board.get_health(&SnakeId(0)) <= self.min_health_threshold;
-            let snake_1_is_dead = self.board.get_health(&SnakeId(1)) <= self.min_health_threshold;
+            let snake_0_is_dead = self.board.get_health(&SnakeId(0)) <= self.terminal.min_health_threshold;
+            let snake_1_is_dead = self.board.get_health(&SnakeId(1)) <= self.terminal.min_health_threshold;
             if snake_0_is_dead || snake_1_is_dead {
                 is_over = true;
                 winner = if snake_0_is_dead && snake_1_is_dead { None } else if snake_0_is_dead { Some(SnakeId(1)) } else { Some(SnakeId(0)) };
-            }
-        }
-        if is_over {
-            match winner {
-                None => {
-                    1e-4
-                }
-                Some(winner) => {
-                    if winner == game::player_to_snake(player_id)
-                    {
-                        1.0
-                    } else {
-                        -1.0
+                for (idx, is_dead) in [snake_0_is_dead, snake_1_is_dead].into_iter().enumerate() {
+                    if is_dead {
+                        causes[idx] = Some(EndCause::HealthThreshold);
                     }
                 }
             }
-        } else {
-            0.0
         }
+        let turn = self.turn;
+        if !is_over {
+            if self.terminal.max_turns.is_some_and(|max_turns| turn >= max_turns) {
+                let cause = EndCause::TurnLimit;
+                let score = self.terminal.evaluator.evaluate(&self.board, player_snake);
+                return Some(if score > 0.0 {
+                    GameOutcome::Win { turn, cause }
+                } else if score < 0.0 {
+                    GameOutcome::Loss { turn, cause }
+                } else {
+                    GameOutcome::Draw { turn, cause }
+                });
+            }
+            return None;
+        }
+        let own_cause = causes[player_snake.0 as usize].unwrap_or(EndCause::Unknown);
+        let opponent_cause = causes[opponent_snake.0 as usize].unwrap_or(EndCause::Unknown);
+        Some(match winner {
+            None => GameOutcome::Draw { turn, cause: own_cause },
+            Some(winner) if winner == player_snake => GameOutcome::Win { turn, cause: opponent_cause },
+            Some(_) => GameOutcome::Loss { turn, cause: own_cause },
+        })
+    }
+
+    // Value of a finished game for player_id, None while the game is running
+    pub fn get_game_ended(&self, player_id: i32) -> Option<f32> {
+        let outcome = self.get_game_outcome(player_id)?;
+        if outcome.cause() == EndCause::TurnLimit {
+            return Some(self.terminal.evaluator.evaluate(&self.board, game::player_to_snake(player_id)));
+        }
+        Some(outcome.value(self.terminal.draw_value))
+    }
+
+    // Outcome of a finished game for each snake, indexed by SnakeId, None while the game is running
+    pub fn get_snake_outcomes(&self) -> Option<[GameOutcome; 2]> {
+        Some([self.get_game_outcome(1)?, self.get_game_outcome(-1)?])
+    }
+
+    // Value target of a finished game for each snake, indexed by SnakeId, each from the snake's own point of view
+    pub fn get_snake_values(&self) -> Option<[f32; 2]> {
+        Some([self.get_game_ended(1)?, self.get_game_ended(-1)?])
     }
 
     pub fn get_snake_head_and_body(&self, snake_id: &SnakeId) -> (Option<Position>, Option<Vec<Position>>) {
         if self.board.is_alive(snake_id) {
             let head = self.board.get_head_as_position(snake_id);
-            let body = self.board.get_snake_body_iter(snake_id).map(|cell_index| cell_index.into_position(BOARD_SIZE as u8)).collect_vec();
+            let body = self.board.get_snake_body_iter(snake_id).map(|cell_index| self.board.position_from_native(cell_index)).collect_vec();
             (Some(head), Some(body))
         } else {
             (None, None)
@@ -339,7 +410,7 @@ impl CanonicalBoard {
         (self_head, self_body, other_head, other_body, foods)
     }
 
-    pub fn get_next_state(&self, action: usize, in_mcts: bool) -> (CanonicalBoard, i32) {
+    pub fn get_next_state(&self, action: usize, in_mcts: bool) -> (CanonicalBoard<B>, i32) {
         let action = Move::from_index(action);
         let next_board = self.play_action(action, in_mcts);
         let next_player = next_board.get_current_player();
@@ -360,7 +431,7 @@ impl CanonicalBoard {
     }
 
 
-    pub fn play_action(&self, action: Move, in_mcts: bool) -> CanonicalBoard {
+    pub fn play_action(&self, action: Move, in_mcts: bool) -> CanonicalBoard<B> {
         if let Some(prev_action) = self.prev_action {
             let actions = if self.first_player == 1 {
                 [prev_action, action]
@@ -368,7 +439,10 @@ impl CanonicalBoard {
                 [action, prev_action]
             };
             let next_board = self.board.simulate_moves(&actions, in_mcts);
-            CanonicalBoard::new(next_board, self.first_player, None, self.min_health_threshold)
+            let mut next_state = CanonicalBoard::new(next_board, self.first_player, None, self.terminal);
+            next_state.turn = self.turn + 1;
+            next_state.death_causes = get_death_causes(&self.board, &next_board, &actions);
+            next_state
         } else {
             let mut new_state = *self;
             new_state.prev_action = Some(action);
@@ -376,3 +450,71 @@ impl CanonicalBoard {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use battlesnake_game_types::types::Move;
+
+    use super::CanonicalBoard;
+    use crate::game::{test_board, Board7x7};
+    use crate::outcome::{EndCause, GameOutcome};
+    use crate::terminal::TerminalConfig;
+
+    // 7x7 board without food
+    fn board(body_0: &str, health_0: u8, body_1: &str, health_1: u8) -> Board7x7 {
+        test_board(body_0, health_0, body_1, health_1, "[]")
+    }
+
+    // Plays one joint move, snake 0 first
+    fn play(board: Board7x7, moves: [Move; 2], terminal: TerminalConfig) -> CanonicalBoard<Board7x7> {
+        CanonicalBoard::new(board, 1, None, terminal).play_action(moves[0], true).play_action(moves[1], true)
+    }
+
+    const LEFT_COLUMN: &str = r#"[{"x":0,"y":3},{"x":0,"y":2},{"x":0,"y":1}]"#;
+    const CENTER_COLUMN: &str = r#"[{"x":3,"y":3},{"x":3,"y":2},{"x":3,"y":1}]"#;
+
+    #[test]
+    fn snake_0_wins_when_snake_1_hits_a_wall() {
+        let state = play(board(CENTER_COLUMN, 100, LEFT_COLUMN, 100), [Move::Up, Move::Left], TerminalConfig::default());
+        let cause = EndCause::Wall;
+        assert_eq!(state.get_snake_outcomes(), Some([GameOutcome::Win { turn: 1, cause }, GameOutcome::Loss { turn: 1, cause }]));
+        assert_eq!(state.get_snake_values(), Some([1.0, -1.0]));
+    }
+
+    #[test]
+    fn snake_0_loses_when_it_hits_a_wall() {
+        let state = play(board(LEFT_COLUMN, 100, CENTER_COLUMN, 100), [Move::Left, Move::Up], TerminalConfig::default());
+        let cause = EndCause::Wall;
+        assert_eq!(state.get_snake_outcomes(), Some([GameOutcome::Loss { turn: 1, cause }, GameOutcome::Win { turn: 1, cause }]));
+        assert_eq!(state.get_snake_values(), Some([-1.0, 1.0]));
+    }
+
+    #[test]
+    fn head_to_head_of_equal_lengths_is_a_draw_for_both() {
+        let terminal = TerminalConfig { draw_value: -0.25, ..TerminalConfig::default() };
+        let state = play(
+            board(r#"[{"x":2,"y":3},{"x":1,"y":3},{"x":0,"y":3}]"#, 100, r#"[{"x":4,"y":3},{"x":5,"y":3},{"x":6,"y":3}]"#, 100),
+            [Move::Right, Move::Left],
+            terminal,
+        );
+        let cause = EndCause::HeadToHead;
+        assert_eq!(state.get_snake_outcomes(), Some([GameOutcome::Draw { turn: 1, cause }, GameOutcome::Draw { turn: 1, cause }]));
+        assert_eq!(state.get_snake_values(), Some([-0.25, -0.25]));
+    }
+
+    #[test]
+    fn snake_at_the_health_threshold_loses() {
+        let state = CanonicalBoard::new(board(CENTER_COLUMN, 100, LEFT_COLUMN, 40), 1, None, TerminalConfig::new(50));
+        let cause = EndCause::HealthThreshold;
+        assert_eq!(state.get_snake_outcomes(), Some([GameOutcome::Win { turn: 0, cause }, GameOutcome::Loss { turn: 0, cause }]));
+        assert_eq!(state.get_snake_values(), Some([1.0, -1.0]));
+    }
+
+    #[test]
+    fn running_game_has_no_outcome() {
+        let state = CanonicalBoard::new(board(CENTER_COLUMN, 100, LEFT_COLUMN, 100), 1, None, TerminalConfig::new(50));
+        assert_eq!(state.get_snake_outcomes(), None);
+        assert_eq!(state.get_snake_values(), None);
+    }
+}
@@ -1,137 +1,409 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 
+use battlesnake_game_types::types::SnakeId;
 use indicatif::ProgressStyle;
 use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rand::seq::SliceRandom;
 
-use crate::alpha_zero_model::AlphaZeroModel;
+use crate::alpha_zero_model::{AlphaZeroModel, TrainConfig};
 use crate::arena::Arena;
 use crate::Args;
+use crate::canonical_board::{BoardKey, get_policy_symmetries};
+use crate::config::NUM_SYMMETRIES;
+use crate::data_loader::DataLoader;
 use crate::examples_handler::ExamplesHandler;
-use crate::game::{Board, BoardInit, CanCanonical, Sample};
+use crate::game::{AuxTargets, BoardInit, CanCanonical, GameBoard, player_to_snake, Sample};
 use crate::mcts::MCTS;
-use crate::utils::{choose_index_based_on_probability};
+use crate::metrics::MetricsLogger;
+use crate::optimizer::{Optimizer, OptimizerConfig};
+use crate::replay_buffer::ReplayBuffer;
+use crate::run_state::{IterationRecord, RunState};
+use crate::stats::{EpisodeStats, SelfPlayStats};
+use crate::terminal::TerminalConfig;
+use crate::utils::{choose_index_based_on_probability, game_rng, set_game_rng};
 
-pub struct Coach {
+// Symmetries of each position played in self play along with their auxiliary targets
+pub type EpisodeExamples = HashMap<BoardKey, (Vec<Sample>, Vec<AuxTargets>)>;
+
+// Board, snake to move, turn and root visit distribution of a half turn
+type HalfTurn = (BoardKey, SnakeId, u32, [f32; 4]);
+
+pub struct Coach<B: GameBoard> {
+    // trained in place, MCTS searches with snapshots of it
     model: AlphaZeroModel,
-    p_model: AlphaZeroModel,
-    mcts: MCTS,
+    mcts: MCTS<B>,
     args: Args,
-    skip_first_self_play: bool,
+    terminal: TerminalConfig,
+    replay_buffer: ReplayBuffer,
+    optimizer: Optimizer,
+    pub run_state: RunState,
     pub examples_handler: ExamplesHandler,
+    pub metrics: MetricsLogger,
 }
 
-impl Coach {
-    pub fn new(model: AlphaZeroModel, args: &Args) -> Self {
-        let mut examples_handler = ExamplesHandler::new(args.save_dir.clone(), args.num_iters_for_train_examples_history);
-        if args.load_examples {
-            examples_handler.load_examples();
-        }
+impl<B: GameBoard> Coach<B> {
+    pub fn new(mut model: AlphaZeroModel, args: &Args) -> std::io::Result<Self> {
+        let save_dir = PathBuf::from(&args.save_dir);
+        let mut examples_handler = ExamplesHandler::new(args.save_dir.clone(), args.num_iters_for_train_examples_history, B::SIZE, args.examples_compression);
+        let mut optimizer = Optimizer::new(&model, OptimizerConfig::from_args(args));
 
-        Self {
-            model: model.clone(),
-            p_model: model.clone(),
-            mcts: MCTS::new(&model, args.c_puct, args.num_mcts_sims),
+        let saved_state = if args.resume {
+            RunState::load(&save_dir.join("run_state.json"))?
+        } else {
+            None
+        };
+        let run_state = match saved_state {
+            Some(run_state) => {
+                println!("Resuming run at iteration {}", run_state.next_iteration);
+                model.load_checkpoint(&save_dir.join("current.safetensors")).unwrap();
+                optimizer.load(&save_dir.join("current_optimizer.safetensors")).unwrap_or_else(|e| {
+                    println!("Failed to load optimizer state: {}", e);
+                });
+                examples_handler.load_indexes(&run_state.example_indexes);
+                run_state
+            }
+            None => {
+                if args.resume {
+                    println!("No run state found in {}, starting a new run", save_dir.display());
+                }
+                if args.load_examples {
+                    examples_handler.load_examples();
+                }
+                let mut run_state = RunState::new(args.seed.unwrap_or_else(rand::random));
+                // loaded examples stand for the self play of the first iteration
+                run_state.self_play_done = args.load_examples;
+                run_state.example_indexes = examples_handler.loaded_indexes().to_vec();
+                run_state
+            }
+        };
+        println!("Training seed {}", run_state.seed);
+
+        let mut metrics = MetricsLogger::new(&save_dir, args.tensorboard)?;
+        metrics.batch_step = run_state.batch_step;
+        metrics.epoch_step = run_state.epoch_step;
+
+        Ok(Self {
+            mcts: MCTS::new(&model.snapshot(), args.c_puct, args.num_mcts_sims),
+            model,
             args: args.clone(),
-            skip_first_self_play: args.load_examples,
+            terminal: TerminalConfig::from_args(args),
+            replay_buffer: ReplayBuffer::from_args(args),
+            optimizer,
+            run_state,
             examples_handler,
-        }
+            metrics,
+        })
     }
 
-    pub fn execute_episode(&mut self) -> HashMap<[u8;121],  Vec<Sample>> {
-        let mut train_examples: HashMap<[u8;121],  Vec<Sample>> = HashMap::new();
-        let board = Board::init_random_board();
-        let mut current_player = 1;
-        let mut canonical_board = board.as_canonical(current_player, self.args.min_health_threshold);
+    pub fn execute_episode(&mut self) -> (EpisodeExamples, EpisodeStats) {
+        let mut train_examples: HashMap<BoardKey,  Vec<Sample>> = HashMap::new();
+        // half turns in order
+        let mut records: Vec<HalfTurn> = vec![];
+        let board = B::init_random_board();
+        let mut canonical_board = board.as_canonical(1, self.terminal);
+        let start_lengths = [SnakeId(0), SnakeId(1)].map(|id| board.get_length_i64(&id) as u32);
+        let mut lengths = start_lengths;
+        let mut policy_entropies = vec![];
         let mut episode_step = 0;
         loop {
             episode_step += 1;
             let temp = if episode_step < self.args.temp_threshold { 1.0 } else { 0.0 };
             let pi = self.mcts.get_action_prob(&canonical_board, temp);
+            policy_entropies.push(self.mcts.get_policy_entropy(&canonical_board));
+            let visits = self.mcts.get_visit_distribution(&canonical_board);
 
-            let canonical_board_hash = canonical_board.to_hashmap_bytes();
+            let canonical_board_hash = canonical_board.to_hashmap_key();
             train_examples.entry(canonical_board_hash).or_insert_with(|| canonical_board.get_mirroring_and_rotation(&pi));
+            records.push((canonical_board_hash, canonical_board.get_current_snake(), canonical_board.turn, visits));
 
             // chose using the action probabilities of pi
             let action = choose_index_based_on_probability(&pi);
-            (canonical_board, current_player) = canonical_board.get_next_state(action,false);
-            let value = canonical_board.get_game_ended(current_player);
-            if value != 0.0 {
-                train_examples.iter_mut().for_each(|(_, data)| {
-                    data.iter_mut().for_each(|(_, _, player)| {
-                        let player_v: f32 = if current_player as f32 != *player { 1.0 } else { 0.0 };
-                        let b_pow: f32 = -1.0;
-                        let player_value = value * b_pow.powf(player_v);
-                        *player = player_value;
-                    });
-                });
-                return train_examples;
+            (canonical_board, _) = canonical_board.get_next_state(action,false);
+            for (idx, length) in lengths.iter_mut().enumerate() {
+                let snake_id = SnakeId(idx as u8);
+                if canonical_board.board.is_alive(&snake_id) {
+                    *length = canonical_board.board.get_length_i64(&snake_id) as u32;
+                }
+            }
+            if let Some(outcomes) = canonical_board.get_snake_outcomes() {
+                let values = canonical_board.get_snake_values().unwrap();
+                let episode_examples = episode_examples(train_examples, &records, values, lengths, canonical_board.turn);
+                let stats = EpisodeStats {
+                    outcome: outcomes[0],
+                    food_eaten: [0, 1].map(|idx| lengths[idx] - start_lengths[idx]),
+                    final_lengths: lengths,
+                    policy_entropies,
+                };
+                return (episode_examples, stats);
             }
         }
     }
 
 
     pub fn learn(&mut self) -> std::io::Result<()> {
-        for iteration in 1..self.args.num_iterations {
+        self.save_run_state()?;
+        for iteration in self.run_state.next_iteration..self.args.num_iterations {
+            self.terminal.min_health_threshold = self.get_min_health_threshold(iteration);
+            if self.args.min_health_threshold_final.is_some() {
+                println!("MIN HEALTH THRESHOLD : {}", self.terminal.min_health_threshold);
+            }
+            set_game_rng(self.run_state.game_rng.clone());
             // self play
-            if !self.skip_first_self_play || iteration > 1 {
+            if !self.run_state.self_play_done {
                 // create a dequeue with max size of num_examples_history
 
-                let mut train_examples: HashMap<[u8;121],  Vec<Sample>> = HashMap::new();
+                let mut train_examples = EpisodeExamples::new();
                 let pb = indicatif::ProgressBar::new(self.args.num_episodes as u64);
                 pb.set_style(ProgressStyle::default_bar()
                     .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>7}/{len:7} ({eta})")
                     .unwrap()
                     .progress_chars("#>-"));
                 let mut sum_episodes_length = 0f32;
+                let mut self_play_stats = SelfPlayStats::new(iteration);
+                // the weights do not change during self play, every episode searches with the same copy
+                let snapshot = self.model.snapshot();
 
                 for _ in 0..self.args.num_episodes {
-                    self.mcts = MCTS::new(&self.model, self.args.c_puct, self.args.num_mcts_sims);
+                    self.mcts = MCTS::new(&snapshot, self.args.c_puct, self.args.num_mcts_sims);
 
-                    let temp_examples = self.execute_episode();
+                    let (temp_examples, episode_stats) = self.execute_episode();
+                    self_play_stats.record(episode_stats);
                     sum_episodes_length += temp_examples.len() as f32  ;
                     for (k, v) in temp_examples.into_iter(){
                         train_examples.entry(k).or_insert(v);
                     }
                     pb.inc(1);
                 }
-                self.mcts = MCTS::new(&self.model, self.args.c_puct, self.args.num_mcts_sims);
+                self.mcts = MCTS::new(&snapshot, self.args.c_puct, self.args.num_mcts_sims);
                 pb.finish();
                 println!("AVG EP LENGTH : {:.2}", sum_episodes_length / self.args.num_episodes as f32);
-                self.examples_handler.save_example(train_examples.into_values().flatten().collect_vec());
+                println!("SELF PLAY (PLAYER 1) {}", self_play_stats.outcomes);
+                let (food_eaten, final_lengths) = (self_play_stats.mean_food_eaten(), self_play_stats.mean_final_lengths());
+                println!("AVG FOOD EATEN : {:.2} / {:.2} ; AVG FINAL LENGTH : {:.2} / {:.2} ; AVG POLICY ENTROPY : {:.3}",
+                         food_eaten[0], food_eaten[1], final_lengths[0], final_lengths[1], self_play_stats.mean_policy_entropy);
+                self_play_stats.save(&PathBuf::from(&self.args.save_dir).join("self_play_stats.jsonl"))?;
+                self.metrics.log("self_play", iteration as u64, &[
+                    ("avg_turns", self_play_stats.outcomes.avg_turns() as f64),
+                    ("policy_entropy", self_play_stats.mean_policy_entropy as f64),
+                ]);
+                let (samples, aux): (Vec<Vec<Sample>>, Vec<Vec<AuxTargets>>) = train_examples.into_values().unzip();
+                self.examples_handler.save_example(samples.into_iter().flatten().collect_vec(), Some(aux.into_iter().flatten().collect_vec()))?;
+                self.run_state.self_play_done = true;
+                self.run_state.game_rng = game_rng();
+                self.run_state.example_indexes = self.examples_handler.loaded_indexes().to_vec();
+                self.save_run_state()?;
             }
 
+            // training is reproducible from the seed and the iteration
+            let iteration_seed = self.run_state.seed.wrapping_add(iteration as u64);
+            tch::manual_seed(iteration_seed as i64);
+            let mut rng = StdRng::seed_from_u64(iteration_seed);
 
-            let mut train_examples = self.examples_handler.examples.clone().into_iter().flatten().collect::<Vec<Sample>>();
-            train_examples.shuffle(&mut rand::thread_rng());
+            let dataset = self.examples_handler.dataset();
+            let iterations = self.examples_handler.loaded_indexes().last().map_or(0, |index| index + 1);
+            let positions = self.replay_buffer.sample(&dataset, iterations, &self.model, self.args.batch_size, &mut rng);
+            let (train_ids, validation_ids) = self.split_positions(positions, &mut rng);
+            let train_config = TrainConfig::from_args(&self.args);
+            let loader = DataLoader::new(dataset, train_ids, train_config.batch_size, train_config.prefetch_batches);
 
             self.model.save_checkpoint(&PathBuf::from(&self.args.save_dir).join("temp.safetensors"))?;
-            self.p_model.load_checkpoint(&PathBuf::from(&self.args.save_dir).join("temp.safetensors"))?;
+            let p_model = self.model.snapshot();
 
 
-            self.model.train(train_examples, self.args.learning_rate, self.args.num_epochs, self.args.batch_size);
+            self.model.train(&loader, &validation_ids, &mut self.optimizer, train_config, &mut self.metrics, &mut rng);
 
-            let mcts = MCTS::new(&self.model,  self.args.c_puct, self.args.num_mcts_sims / 2);
-            let p_mcts = MCTS::new(&self.p_model,  self.args.c_puct, self.args.num_mcts_sims / 2);
+            let mcts = MCTS::<B>::new(&self.model.snapshot(),  self.args.c_puct, self.args.num_mcts_sims / 2);
+            let p_mcts = MCTS::new(&p_model,  self.args.c_puct, self.args.num_mcts_sims / 2);
 
-            let mut arena = Arena::new(mcts, Some(p_mcts), self.args.min_health_threshold);
+            let mut arena = Arena::new(mcts, Some(p_mcts), self.terminal);
             let (n_wins, p_wins, draws) = arena.play_games(self.args.arena_compare);
             println!("NEW/PREV WINS : {} / {} ; DRAWS : {}", n_wins, p_wins, draws);
 
-            if p_wins + n_wins == 0 || (n_wins as f32 / (p_wins + n_wins) as f32) < self.args.update_threshold {
+            let accepted = !(p_wins + n_wins == 0 || (n_wins as f32 / (p_wins + n_wins) as f32) < self.args.update_threshold);
+            self.metrics.log("arena", iteration as u64, &[
+                ("new_wins", n_wins as f64),
+                ("prev_wins", p_wins as f64),
+                ("draws", draws as f64),
+                ("accepted", if accepted { 1.0 } else { 0.0 }),
+            ]);
+            self.metrics.flush();
+
+            if !accepted {
                 println!("REJECTING NEW MODEL");
                 self.model.load_checkpoint(&PathBuf::from(&self.args.save_dir).join("temp.safetensors"))?;
             } else {
                 println!("ACCEPTING NEW MODEL");
                 self.model.save_checkpoint(&PathBuf::from(&self.args.save_dir).join(self.get_checkpoint_file(iteration)))?;
                 self.model.save_checkpoint(&PathBuf::from(&self.args.save_dir).join("best.safetensors"))?;
+                self.optimizer.save(&PathBuf::from(&self.args.save_dir).join(self.get_optimizer_file(iteration))).unwrap_or_else(|e| {
+                    println!("Failed to save optimizer state: {}", e);
+                });
+                self.run_state.last_accepted = Some(self.get_checkpoint_file(iteration));
             }
+
+            self.run_state.history.push(IterationRecord {
+                iteration,
+                accepted,
+                new_wins: n_wins,
+                prev_wins: p_wins,
+                draws,
+            });
+            self.run_state.next_iteration = iteration + 1;
+            self.run_state.self_play_done = false;
+            self.run_state.game_rng = game_rng();
+            self.save_run_state()?;
         }
         Ok(())
     }
 
+    // Supervised training on samples of logged games before the first self play, see game_logs.
+    // The result is saved as pretrained.safetensors and the run continues from it
+    pub fn pretrain(&mut self, samples: Vec<Sample>, epochs: i32) -> std::io::Result<()> {
+        tch::manual_seed(self.run_state.seed as i64);
+        let mut rng = StdRng::seed_from_u64(self.run_state.seed);
+        let positions = (0..samples.len()).step_by(NUM_SYMMETRIES).collect::<Vec<usize>>();
+        let (train_ids, validation_ids) = self.split_positions(positions, &mut rng);
+        let train_config = TrainConfig {
+            epochs,
+            ..TrainConfig::from_args(&self.args)
+        };
+        let loader = DataLoader::new(Arc::new(samples), train_ids, train_config.batch_size, train_config.prefetch_batches);
+        println!("PRETRAINING ON {} SAMPLES", loader.dataset().len());
+        self.model.train(&loader, &validation_ids, &mut self.optimizer, train_config, &mut self.metrics, &mut rng);
+        self.metrics.flush();
+
+        self.model.save_checkpoint(&PathBuf::from(&self.args.save_dir).join("pretrained.safetensors"))?;
+        self.run_state.pretrained = true;
+        self.save_run_state()
+    }
+
+    // Shuffled sample ids to train and validate on, split by position so that no symmetry of a validation
+    // position is trained on. Positions are given by the index of their first sample
+    fn split_positions(&self, mut positions: Vec<usize>, rng: &mut StdRng) -> (Vec<usize>, Vec<usize>) {
+        positions.shuffle(rng);
+        let validation_count = (positions.len() as f32 * self.args.validation_split).round() as usize;
+        let symmetries = |positions: &[usize]| positions.iter().flat_map(|&start| start..start + NUM_SYMMETRIES).collect::<Vec<usize>>();
+        let validation_ids = symmetries(&positions.split_off(positions.len() - validation_count));
+        (symmetries(&positions), validation_ids)
+    }
+
+    // Save the current model, optimizer and run state so that --resume can continue from here
+    fn save_run_state(&mut self) -> std::io::Result<()> {
+        let save_dir = PathBuf::from(&self.args.save_dir);
+        self.model.save_checkpoint(&save_dir.join("current.safetensors"))?;
+        self.optimizer.save(&save_dir.join("current_optimizer.safetensors")).unwrap_or_else(|e| {
+            println!("Failed to save optimizer state: {}", e);
+        });
+        self.run_state.batch_step = self.metrics.batch_step;
+        self.run_state.epoch_step = self.metrics.epoch_step;
+        self.run_state.save(&save_dir.join("run_state.json"))
+    }
+
+    pub fn get_min_health_threshold(&self, iteration: i32) -> u8 {
+        let start = self.args.min_health_threshold;
+        match self.args.min_health_threshold_final {
+            Some(end) => {
+                let last_iteration = (self.args.num_iterations - 2).max(1);
+                let progress = ((iteration - 1) as f32 / last_iteration as f32).clamp(0.0, 1.0);
+                (start as f32 + (end as f32 - start as f32) * progress).round() as u8
+            }
+            None => start,
+        }
+    }
+
     pub fn get_checkpoint_file(&self, iteration: i32) -> String {
         format!("checkpoint_{}.safetensors", iteration).to_string()
     }
-}
\ No newline at end of file
+
+    pub fn get_optimizer_file(&self, iteration: i32) -> String {
+        format!("optimizer_{}.safetensors", iteration)
+    }
+}
+
+
+// Examples of a finished game from the samples of its positions, whose value holds the player they were recorded for
+// until the game ends, and its half turns in order. Each sample gets the value of the snake that moved on it, and
+// the auxiliary targets of that snake from the final turn, lengths and the visits of the other snake on the same board
+fn episode_examples(mut train_examples: HashMap<BoardKey, Vec<Sample>>, records: &[HalfTurn], values: [f32; 2], lengths: [u32; 2], final_turn: u32) -> EpisodeExamples {
+    train_examples.values_mut().flatten().for_each(|(_, _, player)| {
+        *player = values[player_to_snake(*player as i32).0 as usize];
+    });
+    // a turn is played in two half turns, the snakes search the same board one after the other
+    assert!(records.len().is_multiple_of(2), "game ended after a half turn");
+    let mut episode_examples = EpisodeExamples::new();
+    for (i, (key, snake_id, turn, _)) in records.iter().enumerate() {
+        let Some(samples) = train_examples.remove(key) else { continue };
+        let (snake, other) = (snake_id.0 as usize, 1 - snake_id.0 as usize);
+        assert_eq!(records[i ^ 1].1, SnakeId(other as u8), "half turn paired with the same snake");
+        let turns_left = (final_turn - turn) as f32;
+        let length_difference = lengths[snake] as f32 - lengths[other] as f32;
+        let aux = get_policy_symmetries(&records[i ^ 1].3).into_iter()
+            .map(|opponent_pi| [turns_left, length_difference, opponent_pi[0], opponent_pi[1], opponent_pi[2], opponent_pi[3]])
+            .collect();
+        episode_examples.insert(*key, (samples, aux));
+    }
+    episode_examples
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIS: [[f32; 4]; 4] = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+
+    // Two turns of a game, each position recorded with the player to move as value
+    fn game() -> (HashMap<BoardKey, Vec<Sample>>, Vec<HalfTurn>) {
+        let records = (0..4).map(|i| (i as BoardKey, SnakeId(i as u8 % 2), i as u32 / 2, PIS[i])).collect::<Vec<HalfTurn>>();
+        let train_examples = records.iter()
+            .map(|(key, snake_id, _, _)| {
+                let player = if *snake_id == SnakeId(0) { 1.0 } else { -1.0 };
+                (*key, vec![(vec![vec![0.0; 7]; 7], [0.25; 4], player); NUM_SYMMETRIES])
+            })
+            .collect();
+        (train_examples, records)
+    }
+
+    #[test]
+    fn samples_are_valued_for_the_snake_that_moved() {
+        let (train_examples, records) = game();
+        let examples = episode_examples(train_examples, &records, [1.0, -1.0], [5, 3], 3);
+        assert_eq!(examples.len(), 4);
+        for (key, snake_id, turn, _) in &records {
+            let (samples, aux) = &examples[key];
+            let (value, length_difference) = if *snake_id == SnakeId(0) { (1.0, 2.0) } else { (-1.0, -2.0) };
+            assert!(samples.iter().all(|(_, _, sample_value)| *sample_value == value));
+            assert_eq!(aux.len(), NUM_SYMMETRIES);
+            // the first symmetry is the position itself, the opponent being the other half turn of the turn
+            let opponent_pi = PIS[*key as usize ^ 1];
+            assert_eq!(aux[0], [(3 - turn) as f32, length_difference, opponent_pi[0], opponent_pi[1], opponent_pi[2], opponent_pi[3]]);
+        }
+    }
+
+    #[test]
+    fn draws_value_both_snakes_alike() {
+        let (train_examples, records) = game();
+        let examples = episode_examples(train_examples, &records, [-0.25, -0.25], [3, 3], 2);
+        assert!(examples.values().flat_map(|(samples, _)| samples).all(|(_, _, value)| *value == -0.25));
+    }
+
+    #[test]
+    #[should_panic(expected = "game ended after a half turn")]
+    fn games_must_end_after_whole_turns() {
+        let (train_examples, records) = game();
+        episode_examples(train_examples, &records[..3], [1.0, -1.0], [3, 3], 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "half turn paired with the same snake")]
+    fn half_turns_must_alternate_snakes() {
+        let (train_examples, mut records) = game();
+        records[1].1 = SnakeId(0);
+        episode_examples(train_examples, &records, [1.0, -1.0], [3, 3], 2);
+    }
+}
+
@@ -1,10 +1,19 @@
 pub const ACTION_SIZE: i64 = 4;
 // Number of possible actions (up, down, left, right)
-pub const BOARD_SIZE: i64 = 11;     // Board size (11x11)
+pub const BOARD_SIZE: i64 = 11;     // Default board size (11x11)
 
 
 pub const EPS: f32 = 1e-8;
 
+// Rotations and mirrors stored next to each other for every position
+pub const NUM_SYMMETRIES: usize = 8;
+
+// Auxiliary targets of a sample: turns until the end, final length difference and opponent policy (4)
+pub const AUX_TARGETS: usize = 6;
+// the scalar auxiliary targets are predicted divided by these
+pub const AUX_TURNS_SCALE: f32 = 100.0;
+pub const AUX_LENGTH_SCALE: f32 = 10.0;
+
 
 pub const DROPOUT: f64 = 0.3;
 pub const NUM_CHANNELS: i64 = 512;
@@ -0,0 +1,168 @@
+use std::sync::Arc;
+use std::sync::mpsc::sync_channel;
+use std::thread;
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+use tch::{Device, Tensor};
+
+use crate::config::AUX_TARGETS;
+use crate::dataset::Dataset;
+
+// Boards, target policies and target values of a batch
+pub struct Batch {
+    pub boards: Tensor,
+    pub pis: Tensor,
+    pub values: Tensor,
+    // auxiliary targets, zeros for the samples whose aux_mask is 0
+    pub aux: Tensor,
+    pub aux_mask: Tensor,
+}
+
+impl Batch {
+    // Built on the CPU straight from the dataset, without intermediate arrays
+    pub fn from_dataset(dataset: &impl Dataset, ids: &[usize]) -> Self {
+        let board_size = dataset.board_size() as i64;
+        let mut boards = Vec::with_capacity(ids.len() * (board_size * board_size) as usize);
+        let mut pis = Vec::with_capacity(ids.len() * 4);
+        let mut values = Vec::with_capacity(ids.len());
+        let mut aux = Vec::with_capacity(ids.len() * AUX_TARGETS);
+        let mut aux_mask = Vec::with_capacity(ids.len());
+        for &id in ids {
+            values.push(dataset.append_sample(id, &mut boards, &mut pis));
+            let targets = dataset.aux_targets(id);
+            aux.extend(targets.unwrap_or_default());
+            aux_mask.push(if targets.is_some() { 1.0f32 } else { 0.0 });
+        }
+        Batch {
+            boards: Tensor::from_slice(&boards).view([-1, board_size, board_size]),
+            pis: Tensor::from_slice(&pis).view([-1, 4]),
+            values: Tensor::from_slice(&values),
+            aux: Tensor::from_slice(&aux).view([-1, AUX_TARGETS as i64]),
+            aux_mask: Tensor::from_slice(&aux_mask),
+        }
+    }
+
+    pub fn to_device(self, device: Device) -> Self {
+        if !device.is_cuda() {
+            return self;
+        }
+        Batch {
+            boards: self.boards.to_device(device),
+            pis: self.pis.to_device(device),
+            values: self.values.to_device(device),
+            aux: self.aux.to_device(device),
+            aux_mask: self.aux_mask.to_device(device),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.size()[0] as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+
+// Goes over the given samples of a dataset once per epoch, in shuffled disjoint batches.
+// The last incomplete batch is dropped so that every batch has the same size
+pub struct DataLoader<D: Dataset + 'static> {
+    dataset: Arc<D>,
+    ids: Vec<usize>,
+    batch_size: usize,
+    // batches built ahead on a separate thread, 0 builds them on the training thread
+    prefetch: usize,
+}
+
+impl<D: Dataset + 'static> DataLoader<D> {
+    pub fn new(dataset: Arc<D>, ids: Vec<usize>, batch_size: usize, prefetch: usize) -> Self {
+        DataLoader {
+            dataset,
+            ids,
+            batch_size,
+            prefetch,
+        }
+    }
+
+    pub fn dataset(&self) -> &D {
+        &self.dataset
+    }
+
+    pub fn batch_count(&self) -> usize {
+        self.ids.len() / self.batch_size
+    }
+
+    pub fn epoch(&self, rng: &mut impl Rng) -> Box<dyn Iterator<Item=Batch>> {
+        let mut order = self.ids.clone();
+        order.shuffle(rng);
+        let dataset = self.dataset.clone();
+        let batch_size = self.batch_size;
+        let batches = (0..self.batch_count()).map(move |i| {
+            Batch::from_dataset(dataset.as_ref(), &order[i * batch_size..(i + 1) * batch_size])
+        });
+        if self.prefetch == 0 {
+            return Box::new(batches);
+        }
+        let (sender, receiver) = sync_channel(self.prefetch);
+        thread::spawn(move || {
+            for batch in batches {
+                // the receiver is gone when the epoch was stopped early
+                if sender.send(batch).is_err() {
+                    break;
+                }
+            }
+        });
+        Box::new(receiver.into_iter())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::game::Sample;
+
+    // Samples whose value is their id
+    fn dataset(len: usize) -> Arc<Vec<Sample>> {
+        Arc::new((0..len).map(|id| (vec![vec![0.0; 7]; 7], [0.25; 4], id as f32)).collect())
+    }
+
+    // Ids of the samples of each batch of an epoch
+    fn epoch_ids(loader: &DataLoader<Vec<Sample>>, seed: u64) -> Vec<Vec<usize>> {
+        loader.epoch(&mut StdRng::seed_from_u64(seed))
+            .map(|batch| Vec::<f32>::try_from(batch.values).unwrap().into_iter().map(|value| value as usize).collect())
+            .collect()
+    }
+
+    #[test]
+    fn epoch_yields_full_batches_of_distinct_ids() {
+        // 22 ids in batches of 4, the last 2 are dropped
+        let ids = (0..23).filter(|id| *id != 5).collect::<Vec<usize>>();
+        for prefetch in [0, 2] {
+            let loader = DataLoader::new(dataset(23), ids.clone(), 4, prefetch);
+            assert_eq!(loader.batch_count(), 5);
+            let batches = epoch_ids(&loader, 1);
+            assert_eq!(batches.len(), 5);
+            assert!(batches.iter().all(|batch| batch.len() == 4));
+            let seen = batches.iter().flatten().copied().collect::<HashSet<usize>>();
+            assert_eq!(seen.len(), 20);
+            assert!(seen.iter().all(|id| ids.contains(id)));
+        }
+    }
+
+    #[test]
+    fn epochs_are_shuffled_by_the_rng() {
+        let loader = DataLoader::new(dataset(64), (0..64).collect(), 8, 0);
+        assert_eq!(epoch_ids(&loader, 1), epoch_ids(&loader, 1));
+        assert_ne!(epoch_ids(&loader, 1), epoch_ids(&loader, 2));
+        let prefetched = DataLoader::new(dataset(64), (0..64).collect(), 8, 3);
+        assert_eq!(epoch_ids(&prefetched, 1), epoch_ids(&loader, 1));
+    }
+}
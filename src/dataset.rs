@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use memmap2::Mmap;
+
+use crate::examples_file::{append_record, Compression, read_header, read_records, record_aux, record_len, record_value};
+use crate::game::{AuxTargets, Sample};
+
+// Random access to training samples, read straight into batch buffers
+pub trait Dataset: Send + Sync {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn board_size(&self) -> usize;
+
+    // Appends the board and policy of a sample to flat batch buffers and returns its value
+    fn append_sample(&self, index: usize, boards: &mut Vec<f32>, pis: &mut Vec<f32>) -> f32;
+
+    fn value(&self, index: usize) -> f32;
+
+    // Auxiliary targets of a sample, None when they were not recorded
+    fn aux_targets(&self, _index: usize) -> Option<AuxTargets> {
+        None
+    }
+}
+
+impl Dataset for Vec<Sample> {
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn board_size(&self) -> usize {
+        self.first().map_or(0, |(board, _, _)| board.len())
+    }
+
+    fn append_sample(&self, index: usize, boards: &mut Vec<f32>, pis: &mut Vec<f32>) -> f32 {
+        let (board, pi, value) = &self[index];
+        board.iter().for_each(|row| boards.extend_from_slice(row));
+        pis.extend_from_slice(pi);
+        *value
+    }
+
+    fn value(&self, index: usize) -> f32 {
+        self[index].2
+    }
+}
+
+
+// Examples files seen as one dataset of fixed size records. Uncompressed files are memory mapped,
+// compressed and legacy ones are decoded in memory as records, which is still far smaller than Vec<Sample>
+pub struct ExamplesDataset {
+    files: Vec<RecordFile>,
+    // index of the first sample of each file
+    starts: Vec<usize>,
+    len: usize,
+    board_size: usize,
+}
+
+struct RecordFile {
+    bytes: RecordBytes,
+    // start of the records in bytes
+    offset: usize,
+    has_aux: bool,
+}
+
+enum RecordBytes {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl RecordBytes {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            RecordBytes::Mapped(mmap) => mmap,
+            RecordBytes::Owned(bytes) => bytes,
+        }
+    }
+}
+
+impl ExamplesDataset {
+    pub fn open(paths: &[PathBuf], board_size: usize) -> std::io::Result<Self> {
+        let mut files = vec![];
+        let mut starts = vec![];
+        let mut len = 0;
+        for path in paths {
+            let header = read_header(path, board_size)?;
+            let file = match header {
+                Some(header) if header.compression == Compression::None => RecordFile {
+                    // examples files are written once and never modified while a run reads them
+                    bytes: RecordBytes::Mapped(unsafe { Mmap::map(&File::open(path)?)? }),
+                    offset: header.records_offset(),
+                    has_aux: header.has_aux,
+                },
+                _ => {
+                    let (records, has_aux) = read_records(path, board_size)?;
+                    RecordFile {
+                        bytes: RecordBytes::Owned(records),
+                        offset: 0,
+                        has_aux,
+                    }
+                }
+            };
+            let count = (file.bytes.as_slice().len() - file.offset) / record_len(board_size, file.has_aux);
+            if header.is_some_and(|header| header.sample_count != count) {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} is truncated", path.display())));
+            }
+            starts.push(len);
+            len += count;
+            files.push(file);
+        }
+        Ok(ExamplesDataset {
+            files,
+            starts,
+            len,
+            board_size,
+        })
+    }
+
+    // Sample indexes of each file, in the order of the paths
+    pub fn file_ranges(&self) -> Vec<Range<usize>> {
+        self.starts.iter()
+            .zip(self.starts.iter().skip(1).chain([&self.len]))
+            .map(|(&start, &end)| start..end)
+            .collect()
+    }
+
+    // Record of a sample and whether it holds auxiliary targets
+    fn record(&self, index: usize) -> (&[u8], bool) {
+        let file_index = self.starts.partition_point(|&start| start <= index) - 1;
+        let file = &self.files[file_index];
+        let record_len = record_len(self.board_size, file.has_aux);
+        let start = file.offset + (index - self.starts[file_index]) * record_len;
+        (&file.bytes.as_slice()[start..start + record_len], file.has_aux)
+    }
+}
+
+impl Dataset for ExamplesDataset {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn board_size(&self) -> usize {
+        self.board_size
+    }
+
+    fn append_sample(&self, index: usize, boards: &mut Vec<f32>, pis: &mut Vec<f32>) -> f32 {
+        append_record(self.record(index).0, self.board_size, boards, pis)
+    }
+
+    fn value(&self, index: usize) -> f32 {
+        record_value(self.record(index).0, self.board_size)
+    }
+
+    fn aux_targets(&self, index: usize) -> Option<AuxTargets> {
+        let (record, has_aux) = self.record(index);
+        has_aux.then(|| record_aux(record, self.board_size))
+    }
+}
+
@@ -0,0 +1,127 @@
+use std::rc::Rc;
+
+use clap::ValueEnum;
+
+use crate::alpha_zero_model::AlphaZeroModel;
+use crate::canonical_board::CanonicalBoard;
+use crate::game::GameBoard;
+use crate::inference::InferenceModel;
+use crate::rollout::RolloutConfig;
+use crate::heuristics::HeuristicEvaluator;
+use crate::torchscript::TorchScriptModel;
+
+// Prior policy and value of a position for MCTS, both from the view of the snake to move
+pub trait Evaluator<B: GameBoard> {
+    fn evaluate(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32);
+
+    fn evaluate_batch(&self, boards: &[CanonicalBoard<B>]) -> Vec<([f32; 4], f32)> {
+        boards.iter().map(|board| self.evaluate(board)).collect()
+    }
+
+    // Also the opponent's policy when the evaluator predicts it, see MCTS::search
+    fn evaluate_with_opponent(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32, Option<[f32; 4]>) {
+        let (policy, value) = self.evaluate(board);
+        (policy, value, None)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum EvaluatorKind {
+    Uniform,
+    Heuristic,
+    Rollout,
+}
+
+
+impl<B: GameBoard> Evaluator<B> for AlphaZeroModel {
+    fn evaluate(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32) {
+        self.predict(board)
+    }
+
+    fn evaluate_batch(&self, boards: &[CanonicalBoard<B>]) -> Vec<([f32; 4], f32)> {
+        self.predict_batch(boards)
+    }
+
+    fn evaluate_with_opponent(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32, Option<[f32; 4]>) {
+        self.predict_with_opponent(board)
+    }
+}
+
+// Cloning the handle is what lets MCTS instances share one model
+impl<B: GameBoard, E: Evaluator<B>> Evaluator<B> for Rc<E> {
+    fn evaluate(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32) {
+        self.as_ref().evaluate(board)
+    }
+
+    fn evaluate_batch(&self, boards: &[CanonicalBoard<B>]) -> Vec<([f32; 4], f32)> {
+        self.as_ref().evaluate_batch(boards)
+    }
+
+    fn evaluate_with_opponent(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32, Option<[f32; 4]>) {
+        self.as_ref().evaluate_with_opponent(board)
+    }
+}
+
+impl<B: GameBoard> Evaluator<B> for InferenceModel {
+    fn evaluate(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32) {
+        self.predict(board)
+    }
+}
+
+// A search cannot go on without its evaluations, so a module failing after it loaded ends the game with its error
+impl<B: GameBoard> Evaluator<B> for TorchScriptModel {
+    fn evaluate(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32) {
+        let (policy, value, _) = Evaluator::<B>::evaluate_with_opponent(self, board);
+        (policy, value)
+    }
+
+    fn evaluate_batch(&self, boards: &[CanonicalBoard<B>]) -> Vec<([f32; 4], f32)> {
+        self.predict_batch(boards).unwrap_or_else(|e| panic!("TorchScript module failed to evaluate a batch: {}", e))
+    }
+
+    fn evaluate_with_opponent(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32, Option<[f32; 4]>) {
+        self.predict_with_opponent(board).unwrap_or_else(|e| panic!("TorchScript module failed to evaluate a board: {}", e))
+    }
+}
+
+
+// Uniform policy over the valid moves and a value of 0, the baseline any evaluator should beat
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UniformEvaluator;
+
+impl<B: GameBoard> Evaluator<B> for UniformEvaluator {
+    fn evaluate(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32) {
+        (uniform_policy(board), 0.0)
+    }
+}
+
+// Uniform policy and the heuristic score of the board as value
+impl<B: GameBoard> Evaluator<B> for HeuristicEvaluator {
+    fn evaluate(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32) {
+        (uniform_policy(board), HeuristicEvaluator::evaluate(self, &board.board, board.get_current_snake()))
+    }
+}
+
+// Uniform policy and the mean result of the rollouts of normal_mcts as value
+#[derive(Clone, Copy, Debug)]
+pub struct RolloutEvaluator {
+    pub rollouts: usize,
+    pub config: RolloutConfig,
+}
+
+impl<B: GameBoard> Evaluator<B> for RolloutEvaluator {
+    fn evaluate(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32) {
+        let snake_id = board.get_current_snake();
+        let total = (0..self.rollouts.max(1))
+            .map(|_| self.config.run(board.board, &snake_id))
+            .sum::<f32>();
+        (uniform_policy(board), total / self.rollouts.max(1) as f32)
+    }
+}
+
+
+pub fn uniform_policy<B: GameBoard>(board: &CanonicalBoard<B>) -> [f32; 4] {
+    let valid_moves = board.get_valid_moves();
+    let count = valid_moves.iter().filter(|valid| **valid).count().max(1) as f32;
+    valid_moves.map(|valid| if valid { 1.0 / count } else { 0.0 })
+}
@@ -0,0 +1,161 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::canonical_board::get_symmetries;
+use crate::config::NUM_SYMMETRIES;
+use crate::examples_file::read_examples;
+use crate::examples_handler::ExamplesHandler;
+use crate::npy::{read_npy, write_npy};
+
+// Examples are exchanged as three arrays: boards [N, size, size], policies [N, 4] and values [N], all f32.
+// They are stored as boards.npy, policies.npy and values.npy in a directory, or in a single .npz archive.
+// There is no Parquet support
+const ARRAYS: [&str; 3] = ["boards.npy", "policies.npy", "values.npy"];
+
+fn is_npz(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "npz")
+}
+
+// Exports all the examples files of the handler directory, returns the number of samples
+pub fn export_examples(handler: &ExamplesHandler, board_size: usize, output: &Path) -> std::io::Result<usize> {
+    let mut boards = vec![];
+    let mut policies = vec![];
+    let mut values = vec![];
+    for index in handler.available_indexes() {
+        for (board, pi, value) in read_examples(&handler.example_path(*index), board_size)? {
+            board.iter().for_each(|row| boards.extend_from_slice(row));
+            policies.extend_from_slice(&pi);
+            values.push(value);
+        }
+    }
+    let count = values.len();
+    let arrays = [
+        (vec![count, board_size, board_size], boards),
+        (vec![count, 4], policies),
+        (vec![count], values),
+    ];
+    if is_npz(output) {
+        let mut zip = ZipWriter::new(BufWriter::new(File::create(output)?));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, (shape, data)) in ARRAYS.iter().zip(arrays.iter()) {
+            zip.start_file(*name, options)?;
+            write_npy(&mut zip, shape, data)?;
+        }
+        zip.finish()?.flush()?;
+    } else {
+        std::fs::create_dir_all(output)?;
+        for (name, (shape, data)) in ARRAYS.iter().zip(arrays.iter()) {
+            let mut file = BufWriter::new(File::create(output.join(name))?);
+            write_npy(&mut file, shape, data)?;
+            file.flush()?;
+        }
+    }
+    Ok(count)
+}
+
+// Imports an external dataset as a new examples file of the handler, returns the number of samples written.
+// Samples must come with their symmetries stored next to each other, as exported, unless augment generates them
+pub fn import_examples(input: &Path, handler: &mut ExamplesHandler, board_size: usize, augment: bool) -> std::io::Result<usize> {
+    let mut arrays = vec![];
+    if is_npz(input) {
+        let mut zip = ZipArchive::new(BufReader::new(File::open(input)?))?;
+        for name in ARRAYS {
+            arrays.push(read_npy(&mut zip.by_name(name)?)?);
+        }
+    } else {
+        for name in ARRAYS {
+            arrays.push(read_npy(&mut BufReader::new(File::open(input.join(name))?))?);
+        }
+    }
+    let [(boards_shape, boards), (policies_shape, policies), (values_shape, values)]: [(Vec<usize>, Vec<f32>); 3] = arrays.try_into().unwrap();
+    let count = values_shape.first().copied().unwrap_or(0);
+    if boards_shape != [count, board_size, board_size] || policies_shape != [count, 4] || values_shape != [count] {
+        return Err(invalid_input(format!("expected boards [{n}, {s}, {s}], policies [{n}, 4] and values [{n}], got {:?}, {:?} and {:?}",
+                                         boards_shape, policies_shape, values_shape, n = count, s = board_size)));
+    }
+    if !augment && count % NUM_SYMMETRIES != 0 {
+        return Err(invalid_input(format!("{} samples are not groups of {} symmetries, import with augmentation", count, NUM_SYMMETRIES)));
+    }
+
+    let cells = board_size * board_size;
+    let mut samples = vec![];
+    for i in 0..count {
+        let board = boards[i * cells..(i + 1) * cells].chunks(board_size).map(|row| row.to_vec()).collect::<Vec<Vec<f32>>>();
+        let pi = [policies[i * 4], policies[i * 4 + 1], policies[i * 4 + 2], policies[i * 4 + 3]];
+        if augment {
+            samples.extend(get_symmetries(&board, &pi, values[i]));
+        } else {
+            samples.push((board, pi, values[i]));
+        }
+    }
+    let written = samples.len();
+    handler.save_example(samples, None)?;
+    Ok(written)
+}
+
+fn invalid_input(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples_file::Compression;
+    use crate::game::Sample;
+    use crate::utils::test_dir;
+
+    // Two positions with their symmetries stored next to each other
+    fn samples() -> Vec<Sample> {
+        let mut board = vec![vec![0.0; 3]; 3];
+        board[0][1] = 1.0;
+        board[2][2] = -2.0;
+        let mut samples = get_symmetries(&board, &[0.1, 0.2, 0.3, 0.4], 1.0);
+        board[1][1] = 0.5;
+        samples.extend(get_symmetries(&board, &[0.0, 1.0, 0.0, 0.0], -0.5));
+        samples
+    }
+
+    fn handler(dir: &Path) -> ExamplesHandler {
+        std::fs::create_dir_all(dir).unwrap();
+        ExamplesHandler::new(dir.to_str().unwrap().to_string(), 10, 3, Compression::None)
+    }
+
+    #[test]
+    fn exported_examples_import_unchanged() {
+        let dir = test_dir("examples_export");
+        let mut source = handler(&dir.join("source"));
+        source.save_example(samples(), None).unwrap();
+        source.save_example(samples()[..8].to_vec(), None).unwrap();
+        let exported = [samples(), samples()[..8].to_vec()].concat();
+        for output in ["arrays", "arrays.npz"] {
+            assert_eq!(export_examples(&source, 3, &dir.join(output)).unwrap(), 24);
+            let mut target = handler(&dir.join(format!("{}_import", output)));
+            assert_eq!(import_examples(&dir.join(output), &mut target, 3, false).unwrap(), 24);
+            assert_eq!(read_examples(&target.example_path(0), 3).unwrap(), exported);
+        }
+    }
+
+    #[test]
+    fn import_augments_and_checks_shapes() {
+        let dir = test_dir("examples_import");
+        let mut source = handler(&dir.join("source"));
+        source.save_example(samples()[..2].to_vec(), None).unwrap();
+        export_examples(&source, 3, &dir.join("arrays.npz")).unwrap();
+
+        let mut target = handler(&dir.join("target"));
+        // 2 samples are not a whole position of symmetries
+        assert_eq!(import_examples(&dir.join("arrays.npz"), &mut target, 3, false).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(import_examples(&dir.join("arrays.npz"), &mut target, 7, true).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(target.available_indexes().is_empty());
+
+        assert_eq!(import_examples(&dir.join("arrays.npz"), &mut target, 3, true).unwrap(), 16);
+        let expected = samples()[..2].iter().flat_map(|(board, pi, value)| get_symmetries(board, pi, *value)).collect::<Vec<Sample>>();
+        assert_eq!(read_examples(&target.example_path(0), 3).unwrap(), expected);
+    }
+}
+
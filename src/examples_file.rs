@@ -0,0 +1,352 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Seek, Write};
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use crate::config::AUX_TARGETS;
+use crate::game::{AuxTargets, Sample};
+
+// Examples file layout, integers little endian:
+// magic "BSEX" | version u16 | board size u16 | sample count u64 | compression u8 | aux u8 | samples
+// Each sample is board size^2 u8 cell codes, 4 f32 policy, 1 f32 value and, when aux is 1, AUX_TARGETS f32
+// auxiliary targets, the samples being compressed as a whole. Version 1 files have no aux byte
+const MAGIC: &[u8; 4] = b"BSEX";
+pub const EXAMPLES_VERSION: u16 = 2;
+
+// Headerless files of older versions are bincode of Vec<([[f32; 11]; 11], [f32; 4], f32)>, fixed size arrays
+// without length prefixes, and always hold 11x11 boards
+const LEGACY_BOARD_SIZE: usize = 11;
+type LegacySample = ([[f32; LEGACY_BOARD_SIZE]; LEGACY_BOARD_SIZE], [f32; 4], f32);
+
+// Board cell values of to_array_board, a cell is stored as its index
+const CELL_VALUES: [f32; 6] = [0.0, 1.0, 2.0, -1.0, -2.0, 0.5];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+}
+
+impl Compression {
+    fn to_byte(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> std::io::Result<Self> {
+        match byte {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Zstd),
+            _ => Err(invalid_data(format!("unknown compression {}", byte))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ExamplesHeader {
+    pub version: u16,
+    pub board_size: usize,
+    pub sample_count: usize,
+    pub compression: Compression,
+    pub has_aux: bool,
+}
+
+impl ExamplesHeader {
+    fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&self.version.to_le_bytes())?;
+        writer.write_all(&(self.board_size as u16).to_le_bytes())?;
+        writer.write_all(&(self.sample_count as u64).to_le_bytes())?;
+        writer.write_all(&[self.compression.to_byte(), self.has_aux as u8])
+    }
+
+    // Length of the header in bytes, where the records start
+    pub fn records_offset(&self) -> usize {
+        if self.version == 1 { 17 } else { 18 }
+    }
+
+    // None when the file does not start with the magic, e.g. the headerless bincode files of older versions.
+    // Files too short to hold a magic, which no legacy file is, and cut headers are rejected
+    pub fn read(reader: &mut impl Read) -> std::io::Result<Option<Self>> {
+        let mut magic = [0u8; 4];
+        read_header_bytes(reader, &mut magic)?;
+        if &magic != MAGIC {
+            return Ok(None);
+        }
+        let mut bytes = [0u8; 13];
+        read_header_bytes(reader, &mut bytes)?;
+        let version = u16::from_le_bytes([bytes[0], bytes[1]]);
+        if version == 0 || version > EXAMPLES_VERSION {
+            return Err(invalid_data(format!("examples format version {} is not supported, this build reads versions 1 to {}", version, EXAMPLES_VERSION)));
+        }
+        let has_aux = if version >= 2 {
+            let mut aux = [0u8; 1];
+            read_header_bytes(reader, &mut aux)?;
+            aux[0] == 1
+        } else {
+            false
+        };
+        Ok(Some(ExamplesHeader {
+            version,
+            board_size: u16::from_le_bytes([bytes[2], bytes[3]]) as usize,
+            sample_count: u64::from_le_bytes(bytes[4..12].try_into().unwrap()) as usize,
+            compression: Compression::from_byte(bytes[12])?,
+            has_aux,
+        }))
+    }
+}
+
+
+fn read_header_bytes(reader: &mut impl Read, bytes: &mut [u8]) -> std::io::Result<()> {
+    reader.read_exact(bytes).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => invalid_data("examples file is too short to hold a header".to_string()),
+        _ => e,
+    })
+}
+
+
+// aux holds the auxiliary targets of each sample, if known
+pub fn write_examples(path: &Path, samples: &[Sample], aux: Option<&[AuxTargets]>, board_size: usize, compression: Compression) -> std::io::Result<()> {
+    if aux.is_some_and(|aux| aux.len() != samples.len()) {
+        return Err(Error::new(ErrorKind::InvalidInput, "one set of auxiliary targets is needed per sample"));
+    }
+    let mut file = BufWriter::new(File::create(path)?);
+    ExamplesHeader {
+        version: EXAMPLES_VERSION,
+        board_size,
+        sample_count: samples.len(),
+        compression,
+        has_aux: aux.is_some(),
+    }.write(&mut file)?;
+    let mut writer: Box<dyn Write> = match compression {
+        Compression::None => Box::new(file),
+        Compression::Zstd => Box::new(zstd::Encoder::new(file, 3)?.auto_finish()),
+    };
+    let mut record = Vec::with_capacity(record_len(board_size, aux.is_some()));
+    for (i, sample) in samples.iter().enumerate() {
+        record.clear();
+        encode_record(sample, aux.map(|aux| &aux[i]), &mut record)?;
+        writer.write_all(&record)?;
+    }
+    writer.flush()
+}
+
+// Header of a file, rejected when written for another board size. None for legacy files
+pub fn read_header(path: &Path, board_size: usize) -> std::io::Result<Option<ExamplesHeader>> {
+    let header = ExamplesHeader::read(&mut BufReader::new(File::open(path)?))?;
+    if let Some(header) = &header {
+        check_board_size(header.board_size, board_size)?;
+    }
+    Ok(header)
+}
+
+// All the sample records of a file, decompressed, and whether they hold auxiliary targets.
+// Rejects files of another format version or board size
+pub fn read_records(path: &Path, board_size: usize) -> std::io::Result<(Vec<u8>, bool)> {
+    let mut file = BufReader::new(File::open(path)?);
+    let header = match ExamplesHeader::read(&mut file)? {
+        Some(header) => header,
+        None => {
+            check_board_size(LEGACY_BOARD_SIZE, board_size)?;
+            file.rewind()?;
+            let samples: Vec<LegacySample> = bincode::deserialize_from(file).map_err(|e| {
+                invalid_data(format!("pre-versioned examples file that is not a valid 11x11 bincode one, regenerate or convert it ({})", e))
+            })?;
+            let mut records = Vec::with_capacity(samples.len() * record_len(board_size, false));
+            for (board, pi, value) in samples {
+                let board = board.iter().map(|row| row.to_vec()).collect();
+                encode_record(&(board, pi, value), None, &mut records)?;
+            }
+            return Ok((records, false));
+        }
+    };
+    check_board_size(header.board_size, board_size)?;
+    let mut reader: Box<dyn Read> = match header.compression {
+        Compression::None => Box::new(file),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+    };
+    let mut records = vec![0u8; header.sample_count * record_len(board_size, header.has_aux)];
+    reader.read_exact(&mut records)?;
+    Ok((records, header.has_aux))
+}
+
+pub fn read_examples(path: &Path, board_size: usize) -> std::io::Result<Vec<Sample>> {
+    let (records, has_aux) = read_records(path, board_size)?;
+    records
+        .chunks_exact(record_len(board_size, has_aux))
+        .map(|record| decode_record(record, board_size))
+        .collect()
+}
+
+fn check_board_size(file_board_size: usize, board_size: usize) -> std::io::Result<()> {
+    if file_board_size != board_size {
+        return Err(invalid_data(format!("examples are for a {}x{} board, expected {}x{}",
+                                        file_board_size, file_board_size, board_size, board_size)));
+    }
+    Ok(())
+}
+
+
+pub fn record_len(board_size: usize, has_aux: bool) -> usize {
+    board_size * board_size + 20 + if has_aux { AUX_TARGETS * 4 } else { 0 }
+}
+
+pub fn encode_record((board, pi, value): &Sample, aux: Option<&AuxTargets>, record: &mut Vec<u8>) -> std::io::Result<()> {
+    for cell in board.iter().flatten() {
+        record.push(encode_cell(*cell)?);
+    }
+    pi.iter().chain([value]).chain(aux.into_iter().flatten()).for_each(|f| record.extend_from_slice(&f.to_le_bytes()));
+    Ok(())
+}
+
+pub fn decode_record(record: &[u8], board_size: usize) -> std::io::Result<Sample> {
+    let cells = board_size * board_size;
+    let mut board = vec![vec![0.0; board_size]; board_size];
+    for (i, code) in record[..cells].iter().enumerate() {
+        board[i / board_size][i % board_size] = decode_cell(*code)?;
+    }
+    let floats = record[cells..cells + 20].chunks_exact(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())).collect::<Vec<f32>>();
+    Ok((board, [floats[0], floats[1], floats[2], floats[3]], floats[4]))
+}
+
+// Appends the board and policy of a record to flat batch buffers and returns its value
+pub fn append_record(record: &[u8], board_size: usize, boards: &mut Vec<f32>, pis: &mut Vec<f32>) -> f32 {
+    let cells = board_size * board_size;
+    boards.extend(record[..cells].iter().map(|code| CELL_VALUES[*code as usize]));
+    pis.extend(record[cells..cells + 16].chunks_exact(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())));
+    record_value(record, board_size)
+}
+
+pub fn record_value(record: &[u8], board_size: usize) -> f32 {
+    let offset = board_size * board_size + 16;
+    f32::from_le_bytes(record[offset..offset + 4].try_into().unwrap())
+}
+
+// Auxiliary targets of a record of a file that has them
+pub fn record_aux(record: &[u8], board_size: usize) -> AuxTargets {
+    let offset = board_size * board_size + 20;
+    let mut aux = [0.0; AUX_TARGETS];
+    for (target, bytes) in aux.iter_mut().zip(record[offset..].chunks_exact(4)) {
+        *target = f32::from_le_bytes(bytes.try_into().unwrap());
+    }
+    aux
+}
+
+fn encode_cell(cell: f32) -> std::io::Result<u8> {
+    CELL_VALUES.iter()
+        .position(|value| *value == cell)
+        .map(|code| code as u8)
+        .ok_or_else(|| invalid_data(format!("board cell {} has no cell code", cell)))
+}
+
+fn decode_cell(code: u8) -> std::io::Result<f32> {
+    CELL_VALUES.get(code as usize).copied().ok_or_else(|| invalid_data(format!("unknown cell code {}", code)))
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    fn samples() -> Vec<Sample> {
+        let board = vec![vec![0.0, 1.0, 2.0], vec![-1.0, -2.0, 0.5], vec![0.0; 3]];
+        vec![(board.clone(), [0.1, 0.2, 0.3, 0.4], -1.0), (board, [0.0, 0.0, 1.0, 0.0], 0.5)]
+    }
+
+    fn aux() -> Vec<AuxTargets> {
+        let mut aux = [[0.0; AUX_TARGETS]; 2];
+        aux[0][0] = 1.0;
+        aux[1][AUX_TARGETS - 1] = -4.0;
+        aux.to_vec()
+    }
+
+    fn read_aux(path: &Path, board_size: usize) -> Option<Vec<AuxTargets>> {
+        let (records, has_aux) = read_records(path, board_size).unwrap();
+        has_aux.then(|| records.chunks_exact(record_len(board_size, true)).map(|record| record_aux(record, board_size)).collect())
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        let dir = test_dir("examples_round_trip");
+        for compression in [Compression::None, Compression::Zstd] {
+            let path = dir.join(format!("{:?}.examples", compression));
+            write_examples(&path, &samples(), None, 3, compression).unwrap();
+            assert_eq!(read_examples(&path, 3).unwrap(), samples());
+            assert_eq!(read_aux(&path, 3), None);
+            let header = read_header(&path, 3).unwrap().unwrap();
+            assert_eq!((header.version, header.sample_count, header.compression, header.has_aux), (EXAMPLES_VERSION, 2, compression, false));
+
+            write_examples(&path, &samples(), Some(&aux()), 3, compression).unwrap();
+            assert_eq!(read_examples(&path, 3).unwrap(), samples());
+            assert_eq!(read_aux(&path, 3), Some(aux()));
+        }
+    }
+
+    #[test]
+    fn version_1_files_have_no_aux_byte() {
+        let path = test_dir("examples_version_1").join("v1.examples");
+        let mut bytes = vec![];
+        ExamplesHeader { version: 1, board_size: 3, sample_count: 2, compression: Compression::None, has_aux: false }.write(&mut bytes).unwrap();
+        // the aux byte written by write is not part of version 1 headers
+        bytes.pop();
+        samples().iter().for_each(|sample| encode_record(sample, None, &mut bytes).unwrap());
+        std::fs::write(&path, bytes).unwrap();
+        assert_eq!(read_header(&path, 3).unwrap().unwrap().records_offset(), 17);
+        assert_eq!(read_examples(&path, 3).unwrap(), samples());
+    }
+
+    #[test]
+    fn unknown_versions_and_board_sizes_are_rejected() {
+        let path = test_dir("examples_rejected").join("examples");
+        write_examples(&path, &samples(), None, 3, Compression::None).unwrap();
+        assert_eq!(read_examples(&path, 7).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(read_header(&path, 7).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[4..6].copy_from_slice(&(EXAMPLES_VERSION + 1).to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(read_examples(&path, 3).unwrap_err().to_string().contains("not supported"));
+        bytes[4..6].copy_from_slice(&0u16.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(read_examples(&path, 3).unwrap_err().to_string().contains("not supported"));
+    }
+
+    #[test]
+    fn short_files_are_rejected() {
+        let path = test_dir("examples_short").join("examples");
+        write_examples(&path, &samples(), Some(&aux()), 3, Compression::None).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        // a cut magic, a cut header and a header without its aux byte
+        for len in [0, 3, 10, 17] {
+            std::fs::write(&path, &bytes[..len]).unwrap();
+            assert_eq!(read_header(&path, 3).unwrap_err().kind(), ErrorKind::InvalidData, "{} bytes", len);
+        }
+        // a header whose samples are missing
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(read_examples(&path, 3).is_err());
+    }
+
+    #[test]
+    fn legacy_files_are_read_as_11x11() {
+        let path = test_dir("examples_legacy").join("legacy");
+        let legacy: Vec<LegacySample> = vec![([[0.5; 11]; 11], [0.25; 4], 1.0); 3];
+        std::fs::write(&path, bincode::serialize(&legacy).unwrap()).unwrap();
+        assert!(read_header(&path, 11).unwrap().is_none());
+        let samples = read_examples(&path, 11).unwrap();
+        assert_eq!(samples, vec![(vec![vec![0.5; 11]; 11], [0.25; 4], 1.0); 3]);
+        assert_eq!(read_aux(&path, 11), None);
+        assert_eq!(read_examples(&path, 7).unwrap_err().kind(), ErrorKind::InvalidData);
+        std::fs::write(&path, b"garbage!").unwrap();
+        assert!(read_examples(&path, 11).unwrap_err().to_string().contains("pre-versioned"));
+    }
+}
+
@@ -1,22 +1,26 @@
 use std::fs;
-use std::fs::File;
-use std::io::BufReader;
 use std::path::PathBuf;
+use std::sync::Arc;
 
-use crate::game::Sample;
+use crate::dataset::ExamplesDataset;
+use crate::examples_file::{Compression, write_examples};
+use crate::game::{AuxTargets, Sample};
 
 pub struct ExamplesHandler {
     root_path: PathBuf,
     max_examples: usize,
+    board_size: usize,
+    compression: Compression,
     current_index: Option<usize>,
     base_indexes: Vec<usize>,
     loaded_indexes: Vec<usize>,
-    pub examples: Vec<Vec<Sample>>,
+    // loaded examples files, memory mapped when uncompressed
+    dataset: Arc<ExamplesDataset>,
 }
 
 
 impl ExamplesHandler {
-    pub fn new(path: String, max_examples: usize) -> ExamplesHandler {
+    pub fn new(path: String, max_examples: usize, board_size: usize, compression: Compression) -> ExamplesHandler {
         let root_path = PathBuf::from(path);
         let mut indexes: Vec<usize> = vec![];
         for entry in fs::read_dir(&root_path).unwrap() {
@@ -36,50 +40,82 @@ impl ExamplesHandler {
         ExamplesHandler {
             root_path,
             max_examples,
+            board_size,
+            compression,
             current_index: indexes.last().cloned(),
             base_indexes: indexes,
-            examples: vec![],
             loaded_indexes: vec![],
+            dataset: Arc::new(ExamplesDataset::open(&[], board_size).unwrap()),
         }
     }
 
 
     pub fn load_examples(&mut self) {
-        self.examples = vec![];
-        self.loaded_indexes = vec![];
         let mut reversed_index = self.base_indexes.clone();
         reversed_index.reverse();
         let mut to_load_indexes= reversed_index.into_iter().take(self.max_examples).collect::<Vec<usize>>();
         to_load_indexes.reverse();
+        self.load_indexes(&to_load_indexes);
+    }
+
+    // Load exactly the given examples, used to resume a run with the examples it was training on
+    pub fn load_indexes(&mut self, to_load_indexes: &[usize]) {
         print!("Loading examples {:?}", to_load_indexes);
-        for index in &to_load_indexes {
-            self.loaded_indexes.push(*index);
-            let file = File::open(&self.root_path.join(format!("examples_{}", index))).unwrap();
-            let reader = BufReader::new(file);
-            self.examples.push(bincode::deserialize_from(reader).unwrap());
-        }
+        self.loaded_indexes = to_load_indexes.to_vec();
+        self.open_dataset();
         println!("\rExamples Loaded{}", " ".repeat(to_load_indexes.len()));
     }
 
+    pub fn loaded_indexes(&self) -> &[usize] {
+        &self.loaded_indexes
+    }
+
+    // Indexes of all the examples files of the directory
+    pub fn available_indexes(&self) -> &[usize] {
+        &self.base_indexes
+    }
+
+    pub fn example_path(&self, index: usize) -> PathBuf {
+        self.root_path.join(format!("examples_{}", index))
+    }
+
+    pub fn dataset(&self) -> Arc<ExamplesDataset> {
+        self.dataset.clone()
+    }
 
-    pub fn save_example(&mut self, example: Vec<Sample>) {
+    fn open_dataset(&mut self) {
+        let paths = self.loaded_indexes.iter().map(|index| self.example_path(*index)).collect::<Vec<PathBuf>>();
+        let dataset = ExamplesDataset::open(&paths, self.board_size).unwrap_or_else(|e| {
+            panic!("Failed to load examples {:?}: {}", self.loaded_indexes, e);
+        });
+        self.dataset = Arc::new(dataset);
+    }
+
+
+    // aux holds the auxiliary targets of each sample when they are known
+    // Fails without registering the file when a sample cannot be encoded, e.g. an unknown board cell value
+    pub fn save_example(&mut self, example: Vec<Sample>, aux: Option<Vec<AuxTargets>>) -> std::io::Result<()> {
         let new_index = match self.current_index {
             Some(index) => index + 1,
             None => 0,
         };
         print!("Saving example {}...", new_index);
-        let file = File::create(self.root_path.join(format!("examples_{}", new_index))).unwrap();
+        let path = self.root_path.join(format!("examples_{}", new_index));
+        if let Err(e) = write_examples(&path, &example, aux.as_deref(), self.board_size, self.compression) {
+            println!("\rFailed to save example {}: {}", new_index, e);
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
         println!("\rExample {} saved    ", new_index);
-        bincode::serialize_into(file, &example).unwrap();
-        self.examples.push(example);
         self.current_index = Some(new_index);
         self.base_indexes.push(new_index);
         self.loaded_indexes.push(new_index);
         if self.loaded_indexes.len() > self.max_examples {
             println!("Removing example {}...", self.loaded_indexes[0]);
-            self.examples.remove(0);
-            fs::remove_file(self.root_path.join(format!("examples_{}", self.loaded_indexes[0]))).unwrap();
+            fs::remove_file(self.root_path.join(format!("examples_{}", self.loaded_indexes[0])))?;
             self.loaded_indexes.remove(0);
         }
+        self.open_dataset();
+        Ok(())
     }
 }
\ No newline at end of file
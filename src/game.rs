@@ -1,19 +1,35 @@
 use std::collections::VecDeque;
-
-use battlesnake_game_types::compact_representation::StandardCellBoard4Snakes11x11;
-use battlesnake_game_types::types::{build_snake_id_map, FoodGettableGame, Move, ReasonableMovesGame, SimulableGame, SimulatorInstruments, SnakeId, StandardFoodPlaceableGame};
-use battlesnake_game_types::wire_representation::{BattleSnake, NestedGame, Position, Ruleset};
+use std::error::Error;
+use std::fmt::{Debug, Display};
+
+use battlesnake_game_types::compact_representation::dimensions::Square;
+use battlesnake_game_types::compact_representation::standard::CellBoard4Snakes7x7;
+use battlesnake_game_types::compact_representation::{StandardCellBoard, StandardCellBoard4Snakes11x11};
+use battlesnake_game_types::types::{build_snake_id_map, FoodGettableGame, HeadGettableGame, HealthGettableGame, LengthGettableGame, Move, PositionGettableGame, ReasonableMovesGame, SimulableGame, SimulatorInstruments, SnakeBodyGettableGame, SnakeId, SnakeIDGettableGame, StandardFoodPlaceableGame, VictorDeterminableGame};
+use battlesnake_game_types::wire_representation::{BattleSnake, Game, NestedGame, Position, Ruleset};
 use itertools::Itertools;
 use rand::prelude::SliceRandom;
 use rand::Rng;
-use rand::rngs::ThreadRng;
 
 use crate::canonical_board::CanonicalBoard;
-use crate::config::BOARD_SIZE;
+use crate::config::AUX_TARGETS;
+use crate::terminal::TerminalConfig;
+use crate::utils::with_game_rng;
+
+pub type Board7x7 = CellBoard4Snakes7x7;
+pub type Board11x11 = StandardCellBoard4Snakes11x11;
+pub type Board19x19 = StandardCellBoard<u16, Square, { 19 * 19 }, 4>;
+
+pub type Board = Board11x11;
+
+// Board rows (top row first), each row indexed by x
+pub type ArrayBoard = Vec<Vec<f32>>;
 
-pub type Board = StandardCellBoard4Snakes11x11;
+pub type Sample = (ArrayBoard, [f32; 4], f32);
 
-pub type Sample = ([[f32; 11]; 11], [f32; 4], f32);
+// Turns until the end of the game, final length difference with the opponent and opponent policy,
+// see config::AUX_TARGETS
+pub type AuxTargets = [f32; AUX_TARGETS];
 
 pub fn player_to_snake(player: i32) -> SnakeId {
     if player == 1 {
@@ -31,23 +47,71 @@ impl SimulatorInstruments for Instruments {
 }
 
 
-pub trait BoardInit {
-    fn init_random_board() -> Board;
-    fn init_start_of_game_board() -> Board;
+// Compact board usable by the whole pipeline, one implementation per supported board size
+pub trait GameBoard: Copy + Debug + Display + Send + Sync
++ SnakeIDGettableGame<SnakeIDType=SnakeId>
++ PositionGettableGame
++ VictorDeterminableGame
++ HeadGettableGame
++ HealthGettableGame<HealthType=u8>
++ LengthGettableGame
++ FoodGettableGame
++ SnakeBodyGettableGame
++ ReasonableMovesGame
++ SimulableGame<Instruments, 4>
++ StandardFoodPlaceableGame
+{
+    // Width and height of the (square) board
+    const SIZE: usize;
+
+    fn from_wire_game(game: &Game) -> Result<Self, Box<dyn Error>>;
+}
+
+impl GameBoard for Board7x7 {
+    const SIZE: usize = 7;
+
+    fn from_wire_game(game: &Game) -> Result<Self, Box<dyn Error>> {
+        game.as_cell_board(&build_snake_id_map(game))
+    }
+}
+
+impl GameBoard for Board11x11 {
+    const SIZE: usize = 11;
+
+    fn from_wire_game(game: &Game) -> Result<Self, Box<dyn Error>> {
+        game.as_cell_board(&build_snake_id_map(game))
+    }
+}
+
+impl GameBoard for Board19x19 {
+    const SIZE: usize = 19;
+
+    fn from_wire_game(game: &Game) -> Result<Self, Box<dyn Error>> {
+        game.as_cell_board(&build_snake_id_map(game))
+    }
 }
 
-pub trait CanCanonical {
-    fn as_canonical(&self, player: i32, min_health_threshold:u8) -> CanonicalBoard;
+
+pub trait BoardInit: Sized {
+    fn init_random_board() -> Self;
+    fn init_start_of_game_board() -> Self;
 }
 
-pub trait MoveBattleSnake {
+pub trait CanCanonical<B: GameBoard> {
+    fn as_canonical(&self, player: i32, terminal: TerminalConfig) -> CanonicalBoard<B>;
+}
+
+pub trait MoveBattleSnake: Sized {
     fn get_available_moves(&self) -> Vec<[Move; 2]>;
 
-    fn simulate_moves(&self, moves: &[Move; 2], in_mcts:bool) -> Board;
+    // Reasonable moves of each snake by SnakeId, Up alone for a snake without any
+    fn get_moves_for_each_snake(&self) -> [Vec<Move>; 2];
+
+    fn simulate_moves(&self, moves: &[Move; 2], in_mcts:bool) -> Self;
 }
 
 
-impl MoveBattleSnake for Board {
+impl<B: GameBoard> MoveBattleSnake for B {
     fn get_available_moves(&self) -> Vec<[Move; 2]> {
         let reasonable_moves = self.reasonable_moves_for_each_snake();
         reasonable_moves.into_iter()
@@ -57,37 +121,47 @@ impl MoveBattleSnake for Board {
             .collect()
     }
 
-    fn simulate_moves(&self, moves: &[Move; 2], in_mcts:bool) -> Board
+    fn get_moves_for_each_snake(&self) -> [Vec<Move>; 2] {
+        let mut moves = [vec![], vec![]];
+        for (snake_id, snake_moves) in self.reasonable_moves_for_each_snake() {
+            moves[snake_id.0 as usize] = snake_moves;
+        }
+        moves.map(|snake_moves| if snake_moves.is_empty() { vec![Move::Up] } else { snake_moves })
+    }
+
+    fn simulate_moves(&self, moves: &[Move; 2], in_mcts:bool) -> B
     {
         let new_state = *self;
         let formatted_moves = moves.iter().enumerate().map(|(idx, &mv)| (SnakeId(idx as u8), [mv])).collect_vec();
         let mut simulated_moves = new_state.simulate_with_moves(&Instruments {}, formatted_moves);
         let mut next_state = simulated_moves.next().unwrap().1;
         if !in_mcts{
-            let mut rng = &mut rand::thread_rng();
-            if next_state.get_all_food_as_native_positions().is_empty() || rng.gen_range(0..20) < 3 {
-                next_state.place_food(&mut rng);
-            }
-
+            with_game_rng(|rng| {
+                if next_state.get_all_food_as_native_positions().is_empty() || rng.gen_range(0..20) < 3 {
+                    next_state.place_food(rng);
+                }
+            });
         }
         next_state
     }
 }
 
 
-pub fn generate_foods(snake_1_head: &Position, snake_2_head: &Position) -> Vec<Position> {
+pub fn generate_foods(snake_1_head: &Position, snake_2_head: &Position, board_size: i32) -> Vec<Position> {
     let mut foods = vec![];
-    let center_coord: (i32, i32) = ((BOARD_SIZE as i32 - 1) / 2, (BOARD_SIZE as i32 - 1) / 2);
-    let mut rng = rand::thread_rng();
-    foods.push(place_food_for_snake(&foods, snake_1_head, center_coord, &mut rng));
-    foods.push(place_food_for_snake(&foods, snake_2_head, center_coord, &mut rng));
+    let center_coord: (i32, i32) = ((board_size - 1) / 2, (board_size - 1) / 2);
+    with_game_rng(|rng| {
+        foods.push(place_food_for_snake(&foods, snake_1_head, center_coord, board_size, rng));
+        foods.push(place_food_for_snake(&foods, snake_2_head, center_coord, board_size, rng));
+    });
     if !foods.contains(&center_coord) {
         foods.push(center_coord);
     }
     foods.iter().map(|(x, y)| Position { x: *x, y: *y }).collect()
 }
 
-pub fn place_food_for_snake(foods: &[(i32, i32)], snake_head: &Position, center_coord: (i32, i32), rng: &mut ThreadRng) -> (i32, i32) {
+pub fn place_food_for_snake(foods: &[(i32, i32)], snake_head: &Position, center_coord: (i32, i32), board_size: i32, rng: &mut impl Rng) -> (i32, i32) {
+    let max_coord = board_size - 1;
     let possible_player_food = vec![
         (snake_head.x - 1, snake_head.y - 1),
         (snake_head.x - 1, snake_head.y + 1),
@@ -96,7 +170,7 @@ pub fn place_food_for_snake(foods: &[(i32, i32)], snake_head: &Position, center_
     ];
     let mut available_food = vec![];
     for p in possible_player_food {
-        if center_coord == p || (p.0 < 0 || p.0 > 10) || (p.1 < 0 || p.1 > 10) {
+        if center_coord == p || (p.0 < 0 || p.0 > max_coord) || (p.1 < 0 || p.1 > max_coord) {
             continue;
         }
         if foods.contains(&p) {
@@ -104,7 +178,7 @@ pub fn place_food_for_snake(foods: &[(i32, i32)], snake_head: &Position, center_
         }
         if ((p.0 < snake_head.x && snake_head.x < center_coord.0)
             || (center_coord.0 < snake_head.x && snake_head.x < p.0)
-            || (p.1 < snake_head.y && snake_head.y < center_coord.1) || (center_coord.1 < snake_head.y && snake_head.y < p.1)) && !((p.0 == 0 || p.0 == 10) && (p.1 == 0 || p.1 == 10)) {
+            || (p.1 < snake_head.y && snake_head.y < center_coord.1) || (center_coord.1 < snake_head.y && snake_head.y < p.1)) && !((p.0 == 0 || p.0 == max_coord) && (p.1 == 0 || p.1 == max_coord)) {
             available_food.push(p);
         }
     }
@@ -113,10 +187,10 @@ pub fn place_food_for_snake(foods: &[(i32, i32)], snake_head: &Position, center_
 }
 
 
-impl BoardInit for Board {
-    fn init_random_board() -> Board {
-        let mut rng = rand::thread_rng();
-        let (mn, md, mx): (i32, i32, i32) = (1, (BOARD_SIZE as i32 - 1) / 2, BOARD_SIZE as i32 - 2);
+impl<B: GameBoard> BoardInit for B {
+    fn init_random_board() -> B {
+        let board_size = B::SIZE as i32;
+        let (mn, md, mx): (i32, i32, i32) = (1, (board_size - 1) / 2, board_size - 2);
         let mut corners = vec![
             Position { x: mn, y: mn },
             Position { x: mn, y: mx },
@@ -129,10 +203,13 @@ impl BoardInit for Board {
             Position { x: md, y: mx },
             Position { x: mx, y: md },
         ];
-        corners.shuffle(&mut rng);
-        cardinal_points.shuffle(&mut rng);
+        let corners_first = with_game_rng(|rng| {
+            corners.shuffle(rng);
+            cardinal_points.shuffle(rng);
+            rng.gen_range(0..2) == 0
+        });
         let mut start_points: Vec<Position> = Vec::new();
-        if rng.gen_range(0..2) == 0 {
+        if corners_first {
             start_points.append(&mut corners);
             start_points.append(&mut cardinal_points);
         } else {
@@ -145,7 +222,7 @@ impl BoardInit for Board {
 
         let player_1_body = VecDeque::from([player_1_head, player_1_head, player_1_head]);
         let player_2_body = VecDeque::from([player_2_head, player_2_head, player_2_head]);
-        let foods = generate_foods(&player_1_head, &player_2_head);
+        let foods = generate_foods(&player_1_head, &player_2_head, board_size);
 
 
         let player_1 = BattleSnake {
@@ -161,8 +238,8 @@ impl BoardInit for Board {
         let  game = battlesnake_game_types::wire_representation::Game {
             turn: 1,
             board: battlesnake_game_types::wire_representation::Board {
-                height: 11,
-                width: 11,
+                height: B::SIZE as u32,
+                width: B::SIZE as u32,
                 food: foods,
                 snakes: vec![
                     player_1.clone(),
@@ -191,25 +268,36 @@ impl BoardInit for Board {
                 source: None,
             },
         };
-        let snake_id_mapping = build_snake_id_map(&game);
-        game.as_cell_board(&snake_id_mapping).unwrap()
+        B::from_wire_game(&game).unwrap()
     }
 
-    fn init_start_of_game_board() -> Board {
+    fn init_start_of_game_board() -> B {
         let file = std::fs::File::open("fixtures/start_of_game.json").unwrap();
         let game: battlesnake_game_types::wire_representation::Game = serde_json::from_reader(file).unwrap();
-        let snake_id_mapping = build_snake_id_map(&game);
-        game.as_cell_board(&snake_id_mapping).unwrap()
+        B::from_wire_game(&game).unwrap()
     }
 }
 
-impl CanCanonical for Board {
-    fn as_canonical(&self, player: i32, min_health_threshold:u8) -> CanonicalBoard {
-        CanonicalBoard::new(*self, player, None, min_health_threshold)
+impl<B: GameBoard> CanCanonical<B> for B {
+    fn as_canonical(&self, player: i32, terminal: TerminalConfig) -> CanonicalBoard<B> {
+        CanonicalBoard::new(*self, player, None, terminal)
     }
 }
 
 
-
-
-
+// 7x7 board of the tests from the wire bodies of both snakes, head first, snake 0 being "you", and the wire food
+#[cfg(test)]
+pub fn test_board(body_0: &str, health_0: u8, body_1: &str, health_1: u8, food: &str) -> Board7x7 {
+    let snake = |id: &str, body: &str, health: u8| {
+        let cells = serde_json::from_str::<Vec<serde_json::Value>>(body).unwrap();
+        let (head, length) = (cells[0].to_string(), cells.len());
+        format!(r#"{{"id":"{id}","name":"{id}","health":{health},"body":{body},"head":{head},"length":{length},"shout":null}}"#)
+    };
+    let (snake_0, snake_1) = (snake("snake_0", body_0, health_0), snake("snake_1", body_1, health_1));
+    let json = format!(
+        r#"{{"game":{{"id":"test","ruleset":{{"name":"standard","version":"v1"}},"timeout":500}},"turn":0,
+        "board":{{"height":7,"width":7,"food":{food},"snakes":[{snake_0},{snake_1}],"hazards":[]}},"you":{snake_0}}}"#
+    );
+    let game: Game = serde_json::from_str(&json).unwrap();
+    Board7x7::from_wire_game(&game).unwrap()
+}
@@ -0,0 +1,237 @@
+use std::path::Path;
+
+use battlesnake_game_types::types::{Move, Vector};
+use battlesnake_game_types::wire_representation::Game;
+use serde_json::Value;
+
+use crate::game::{CanCanonical, GameBoard, Sample};
+use crate::terminal::TerminalConfig;
+
+// Samples of the 2 snakes games logged by the Battlesnake engine (battlesnake play --output) in a directory.
+// A log holds one wire format Game frame per line, or a JSON array of frames, the result line of the engine
+// ({"winnerId": .., "isDraw": ..}) being optional. Each snake gets the move it played as a one hot policy and the
+// final result as value, draw_value for a draw, with all the symmetries of the position.
+// Unreadable logs and the lines, frames and moves that cannot be used are skipped and counted
+pub fn load_game_logs<B: GameBoard>(dir: &Path, draw_value: f32) -> std::io::Result<Vec<Sample>> {
+    let mut samples = vec![];
+    let mut games = 0;
+    let mut skipped = Skipped::default();
+    let mut paths = std::fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect::<std::io::Result<Vec<_>>>()?;
+    paths.sort();
+    for path in paths.iter().filter(|path| path.is_file()) {
+        let game_samples = read_game_log(path, &mut skipped)
+            .and_then(|(frames, winner)| game_samples::<B>(&frames, winner, draw_value, &mut skipped));
+        match game_samples {
+            Ok(game_samples) if game_samples.is_empty() => {}
+            Ok(game_samples) => {
+                games += 1;
+                samples.extend(game_samples);
+            }
+            Err(reason) => {
+                println!("PRETRAIN : skipping {}, {}", path.display(), reason);
+                skipped.files += 1;
+            }
+        }
+    }
+    println!("PRETRAIN : {} samples from {} games in {}", samples.len(), games, dir.display());
+    if skipped != Skipped::default() {
+        println!("PRETRAIN : skipped {} files, {} lines that are not JSON, {} frames that are not games, {} moves that are not a step and {} boards that could not be converted",
+                 skipped.files, skipped.lines, skipped.frames, skipped.moves, skipped.boards);
+    }
+    Ok(samples)
+}
+
+// What could not be used of the logs
+#[derive(Debug, Default, PartialEq)]
+struct Skipped {
+    files: usize,
+    lines: usize,
+    frames: usize,
+    // moves that are not a step to a neighbour cell
+    moves: usize,
+    // positions whose wire game is not a board of the size trained on
+    boards: usize,
+}
+
+// Frames of a log and its winner, Some(None) for a draw and None when the log has no result line.
+// Err is why the whole log is skipped
+fn read_game_log(path: &Path, skipped: &mut Skipped) -> Result<(Vec<Game>, Option<Option<String>>), String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let values = match serde_json::from_str::<Value>(&content) {
+        // a JSON array, pretty printed or not, or a log of a single line
+        Ok(Value::Array(array)) => array,
+        Ok(value) => vec![value],
+        // a pretty printed array that is not valid JSON, its first line being a lone bracket
+        Err(e) if content.trim_start().starts_with('[') && serde_json::from_str::<Value>(content.lines().next().unwrap()).is_err() => {
+            return Err(format!("not a valid JSON array ({})", e));
+        }
+        // one frame per line, lines that are not JSON are skipped with the frames around them kept
+        Err(_) => {
+            let mut values = vec![];
+            for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                match serde_json::from_str::<Value>(line) {
+                    Ok(Value::Array(array)) => values.extend(array),
+                    Ok(value) => values.push(value),
+                    Err(_) => skipped.lines += 1,
+                }
+            }
+            values
+        }
+    };
+
+    let mut frames = vec![];
+    let mut winner = None;
+    for mut value in values {
+        if value.get("isDraw").is_some() || value.get("winnerId").is_some() {
+            let is_draw = value["isDraw"].as_bool().unwrap_or(false);
+            let winner_id = value["winnerId"].as_str().filter(|id| !id.is_empty()).map(|id| id.to_string());
+            winner = Some(if is_draw { None } else { winner_id });
+            continue;
+        }
+        // the engine logs the frames without a you snake, any snake will do as it is replaced per sample
+        if value.get("board").is_some() && value.get("you").is_none() {
+            if let Some(snake) = value["board"]["snakes"].get(0).cloned() {
+                value["you"] = snake;
+            }
+        }
+        match serde_json::from_value::<Game>(value) {
+            Ok(frame) => frames.push(frame),
+            Err(_) => skipped.frames += 1,
+        }
+    }
+    if frames.is_empty() {
+        return Err("no game frames".to_string());
+    }
+    frames.sort_by_key(|frame| frame.turn);
+    Ok((frames, winner))
+}
+
+fn game_samples<B: GameBoard>(frames: &[Game], winner: Option<Option<String>>, draw_value: f32, skipped: &mut Skipped) -> Result<Vec<Sample>, String> {
+    let first = &frames[0];
+    if first.board.snakes.len() != 2 {
+        return Err(format!("{} snakes, only 2 snakes games are used", first.board.snakes.len()));
+    }
+    if first.board.width as usize != B::SIZE || first.board.height as usize != B::SIZE {
+        return Err(format!("{}x{} board, expected {}x{}", first.board.width, first.board.height, B::SIZE, B::SIZE));
+    }
+    // without a result line the sole survivor of the last frame wins
+    let winner = winner.unwrap_or_else(|| {
+        let survivors = &frames.last().unwrap().board.snakes;
+        if survivors.len() == 1 { Some(survivors[0].id.clone()) } else { None }
+    });
+
+    let mut samples = vec![];
+    for (frame, next) in frames.iter().zip(frames.iter().skip(1)) {
+        if frame.board.snakes.len() != 2 {
+            skipped.frames += 1;
+            continue;
+        }
+        for snake in &frame.board.snakes {
+            let Some(next_snake) = next.board.snakes.iter().find(|next_snake| next_snake.id == snake.id) else { continue };
+            let delta = Vector {
+                x: (next_snake.head.x - snake.head.x) as i64,
+                y: (next_snake.head.y - snake.head.y) as i64,
+            };
+            if delta.x.abs() + delta.y.abs() != 1 {
+                skipped.moves += 1;
+                continue;
+            }
+            let mut pi = [0.0; 4];
+            pi[Move::from_vector(delta).as_index()] = 1.0;
+
+            // the snake playing is you, so SnakeId(0) and player 1 of the canonical board
+            let mut game = frame.clone();
+            game.you = snake.clone();
+            let Ok(board) = B::from_wire_game(&game) else {
+                skipped.boards += 1;
+                continue;
+            };
+            let value = match &winner {
+                Some(id) if *id == snake.id => 1.0,
+                Some(_) => -1.0,
+                None => draw_value,
+            };
+            let canonical_board = board.as_canonical(1, TerminalConfig::default());
+            samples.extend(canonical_board.get_mirroring_and_rotation(&pi).into_iter()
+                .map(|(board, pi, _)| (board, pi, value)));
+        }
+    }
+    Ok(samples)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use battlesnake_game_types::types::Move;
+
+    use super::*;
+    use crate::config::NUM_SYMMETRIES;
+    use crate::game::Board7x7;
+    use crate::utils::test_dir;
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from("fixtures/game_logs").join(name)
+    }
+
+    // Checks the symmetries of the sample of a snake whose head is at (x, y) playing mv
+    fn check_position(samples: &[Sample], (x, y): (usize, usize), mv: Move, value: f32) {
+        assert_eq!(samples.len(), NUM_SYMMETRIES);
+        assert!(samples.iter().all(|(_, pi, sample_value)| pi.iter().sum::<f32>() == 1.0 && *sample_value == value));
+        // the first symmetry is the position itself, with the snake playing as the head of value 1
+        let (board, pi, _) = &samples[0];
+        assert_eq!(board[6 - y][x], 1.0);
+        assert_eq!(pi[mv.as_index()], 1.0);
+    }
+
+    fn log_samples(path: &Path, draw_value: f32) -> (Vec<Sample>, Skipped) {
+        let mut skipped = Skipped::default();
+        let (frames, winner) = read_game_log(path, &mut skipped).unwrap();
+        (game_samples::<Board7x7>(&frames, winner, draw_value, &mut skipped).unwrap(), skipped)
+    }
+
+    #[test]
+    fn jsonl_log_keeps_the_frames_around_bad_lines() {
+        let (samples, skipped) = log_samples(&fixture("win.jsonl"), 0.0);
+        assert_eq!(skipped, Skipped { lines: 1, frames: 1, ..Skipped::default() });
+        let positions = samples.chunks(NUM_SYMMETRIES).collect::<Vec<_>>();
+        assert_eq!(positions.len(), 4);
+        check_position(positions[0], (1, 1), Move::Up, 1.0);
+        check_position(positions[1], (5, 5), Move::Down, -1.0);
+        check_position(positions[2], (1, 2), Move::Right, 1.0);
+        check_position(positions[3], (5, 4), Move::Left, -1.0);
+    }
+
+    #[test]
+    fn pretty_printed_array_log_is_read_whole() {
+        let (samples, skipped) = log_samples(&fixture("draw.json"), -0.25);
+        assert_eq!(skipped, Skipped::default());
+        let positions = samples.chunks(NUM_SYMMETRIES).collect::<Vec<_>>();
+        assert_eq!(positions.len(), 4);
+        check_position(positions[0], (4, 1), Move::Left, -0.25);
+        check_position(positions[1], (1, 5), Move::Right, -0.25);
+        check_position(positions[2], (3, 1), Move::Left, -0.25);
+        check_position(positions[3], (2, 5), Move::Up, -0.25);
+    }
+
+    #[test]
+    fn unusable_logs_are_skipped() {
+        let dir = test_dir("game_logs");
+        let array = std::fs::read_to_string(fixture("draw.json")).unwrap();
+        std::fs::write(dir.join("cut.json"), &array[..array.len() / 2]).unwrap();
+        std::fs::write(dir.join("garbage.jsonl"), "not json\n").unwrap();
+        std::fs::write(dir.join("11x11.jsonl"), std::fs::read_to_string(fixture("win.jsonl")).unwrap().replace("\"height\": 7, \"width\": 7", "\"height\": 11, \"width\": 11")).unwrap();
+        std::fs::copy(fixture("win.jsonl"), dir.join("win.jsonl")).unwrap();
+
+        let mut skipped = Skipped::default();
+        assert!(read_game_log(&dir.join("cut.json"), &mut skipped).unwrap_err().contains("not a valid JSON array"));
+        assert_eq!(read_game_log(&dir.join("garbage.jsonl"), &mut skipped).unwrap_err(), "no game frames");
+        let (frames, winner) = read_game_log(&dir.join("11x11.jsonl"), &mut skipped).unwrap();
+        assert!(game_samples::<Board7x7>(&frames, winner, 0.0, &mut skipped).unwrap_err().contains("11x11 board"));
+
+        let samples = load_game_logs::<Board7x7>(&dir, 0.0).unwrap();
+        assert_eq!(samples, log_samples(&fixture("win.jsonl"), 0.0).0);
+    }
+}
+
@@ -0,0 +1,325 @@
+use std::collections::VecDeque;
+
+use battlesnake_game_types::types::SnakeId;
+use battlesnake_game_types::wire_representation::Position;
+
+use crate::Args;
+use crate::game::GameBoard;
+
+// Length, health, area, food and head to head scores of features
+pub const NUM_FEATURES: usize = 5;
+
+const NEIGHBOURS: [(i32, i32); 4] = [(0, 1), (0, -1), (-1, 0), (1, 0)];
+
+// Scores an unfinished game from one snake's point of view, in [-1, 1], as the weighted mean of its features
+#[derive(Clone, Copy, Debug)]
+pub struct HeuristicEvaluator {
+    pub length_weight: f32,
+    pub health_weight: f32,
+    pub area_weight: f32,
+    pub food_weight: f32,
+    pub head_to_head_weight: f32,
+}
+
+impl HeuristicEvaluator {
+    pub fn from_args(args: &Args) -> Self {
+        HeuristicEvaluator {
+            length_weight: args.eval_length_weight,
+            health_weight: args.eval_health_weight,
+            area_weight: args.eval_area_weight,
+            food_weight: args.eval_food_weight,
+            head_to_head_weight: args.eval_head_to_head_weight,
+        }
+    }
+
+    pub fn evaluate<B: GameBoard>(&self, board: &B, snake_id: SnakeId) -> f32 {
+        let weights = [self.length_weight, self.health_weight, self.area_weight, self.food_weight, self.head_to_head_weight];
+        let total_weight: f32 = weights.iter().sum();
+        if total_weight <= 0.0 {
+            return 0.0;
+        }
+        let score: f32 = features(board, snake_id).iter().zip(weights).map(|(feature, weight)| feature * weight).sum();
+        (score / total_weight).clamp(-1.0, 1.0)
+    }
+}
+
+impl Default for HeuristicEvaluator {
+    fn default() -> Self {
+        HeuristicEvaluator {
+            length_weight: 1.0,
+            health_weight: 0.5,
+            area_weight: 1.0,
+            food_weight: 0.0,
+            head_to_head_weight: 0.0,
+        }
+    }
+}
+
+
+// Every heuristic from snake_id's point of view, each in [-1, 1], also usable as extra inputs of a network
+pub fn features<B: GameBoard>(board: &B, snake_id: SnakeId) -> [f32; NUM_FEATURES] {
+    let opponent_id = SnakeId(1 - snake_id.0);
+    let health_score = (board.get_health_i64(&snake_id) - board.get_health_i64(&opponent_id)) as f32 / 100.0;
+
+    let (self_area, other_area) = area_control(board, snake_id, opponent_id);
+    let area_score = (self_area as f32 - other_area as f32) / (B::SIZE * B::SIZE) as f32;
+
+    // an unreachable food counts as twice the board away
+    let unreachable = 2 * B::SIZE as u32;
+    let self_food = food_distance(board, snake_id).unwrap_or(unreachable).min(unreachable);
+    let other_food = food_distance(board, opponent_id).unwrap_or(unreachable).min(unreachable);
+    let food_score = (other_food as f32 - self_food as f32) / unreachable as f32;
+
+    let head_to_head_score = head_to_head_danger(board, opponent_id, snake_id) - head_to_head_danger(board, snake_id, opponent_id);
+
+    [length_advantage(board, snake_id, opponent_id), health_score, area_score, food_score, head_to_head_score]
+}
+
+
+// Length difference relative to the longest snake, in [-1, 1]
+pub fn length_advantage<B: GameBoard>(board: &B, snake_id: SnakeId, opponent_id: SnakeId) -> f32 {
+    let (self_length, other_length) = (board.get_length_i64(&snake_id) as f32, board.get_length_i64(&opponent_id) as f32);
+    (self_length - other_length) / self_length.max(other_length).max(1.0)
+}
+
+
+// Number of moves from the head of snake_id to the nearest food around the snake bodies, None when no food is reachable
+pub fn food_distance<B: GameBoard>(board: &B, snake_id: SnakeId) -> Option<u32> {
+    if !board.is_alive(&snake_id) {
+        return None;
+    }
+    let distances = distances_from(board, board.get_head_as_position(&snake_id));
+    board.get_all_food_as_positions().iter()
+        .map(|food| distances[cell_index::<B>(food)])
+        .filter(|distance| *distance != u32::MAX)
+        .min()
+}
+
+
+// Whether the head of snake_id moving to position can meet the head of opponent_id there without winning the collision
+pub fn head_to_head_risk<B: GameBoard>(board: &B, snake_id: SnakeId, opponent_id: SnakeId, position: &Position) -> bool {
+    if !board.is_alive(&opponent_id) || board.get_length_i64(&snake_id) > board.get_length_i64(&opponent_id) {
+        return false;
+    }
+    let opponent_head = board.get_head_as_position(&opponent_id);
+    (opponent_head.x - position.x).abs() + (opponent_head.y - position.y).abs() == 1
+}
+
+
+// Share of the free cells next to the head of snake_id where a head to head with opponent_id would not be won, in [0, 1]
+pub fn head_to_head_danger<B: GameBoard>(board: &B, snake_id: SnakeId, opponent_id: SnakeId) -> f32 {
+    if !board.is_alive(&snake_id) {
+        return 0.0;
+    }
+    let free_cells = free_neighbours(board, &board.get_head_as_position(&snake_id));
+    let risky = free_cells.iter().filter(|cell| head_to_head_risk(board, snake_id, opponent_id, cell)).count();
+    risky as f32 / free_cells.len().max(1) as f32
+}
+
+
+// Number of free cells each snake reaches strictly before the other one (Voronoi partition)
+pub fn area_control<B: GameBoard>(board: &B, snake_id: SnakeId, opponent_id: SnakeId) -> (usize, usize) {
+    // 0 = free, 1 = reached by snake_id, 2 = reached by opponent_id, 3 = blocked or contested
+    let mut owner = blocked_cells(board).into_iter().map(|blocked| if blocked { 3u8 } else { 0 }).collect::<Vec<u8>>();
+    let mut distance = vec![u32::MAX; B::SIZE * B::SIZE];
+    let mut queue = VecDeque::new();
+
+    for (id, mark) in [(snake_id, 1u8), (opponent_id, 2u8)] {
+        if board.is_alive(&id) {
+            let head = board.get_head_as_position(&id);
+            distance[cell_index::<B>(&head)] = 0;
+            queue.push_back((head, mark));
+        }
+    }
+
+    let mut areas = (0, 0);
+    while let Some((position, mark)) = queue.pop_front() {
+        let index = cell_index::<B>(&position);
+        // a cell contested after it was queued leads nowhere, the heads start in blocked cells
+        if owner[index] == 3 && distance[index] > 0 {
+            continue;
+        }
+        let next_distance = distance[index] + 1;
+        for next in neighbours::<B>(&position) {
+            let next_index = cell_index::<B>(&next);
+            if owner[next_index] == 0 {
+                owner[next_index] = mark;
+                distance[next_index] = next_distance;
+                if mark == 1 { areas.0 += 1 } else { areas.1 += 1 }
+                queue.push_back((next, mark));
+            } else if owner[next_index] != mark && owner[next_index] != 3 && distance[next_index] == next_distance {
+                // reached at the same time by both snakes, nobody owns it
+                if owner[next_index] == 1 { areas.0 -= 1 } else { areas.1 -= 1 }
+                owner[next_index] = 3;
+            }
+        }
+    }
+    areas
+}
+
+
+// Cells next to position that are on the board and not part of a snake
+pub fn free_neighbours<B: GameBoard>(board: &B, position: &Position) -> Vec<Position> {
+    let blocked = blocked_cells(board);
+    neighbours::<B>(position).into_iter().filter(|cell| !blocked[cell_index::<B>(cell)]).collect()
+}
+
+
+// Moves needed to reach each cell from start around the snake bodies, u32::MAX for unreachable cells
+fn distances_from<B: GameBoard>(board: &B, start: Position) -> Vec<u32> {
+    let blocked = blocked_cells(board);
+    let mut distance = vec![u32::MAX; B::SIZE * B::SIZE];
+    distance[cell_index::<B>(&start)] = 0;
+    let mut queue = VecDeque::from([start]);
+    while let Some(position) = queue.pop_front() {
+        let next_distance = distance[cell_index::<B>(&position)] + 1;
+        for next in neighbours::<B>(&position) {
+            let next_index = cell_index::<B>(&next);
+            if !blocked[next_index] && distance[next_index] == u32::MAX {
+                distance[next_index] = next_distance;
+                queue.push_back(next);
+            }
+        }
+    }
+    distance
+}
+
+fn blocked_cells<B: GameBoard>(board: &B) -> Vec<bool> {
+    let mut blocked = vec![false; B::SIZE * B::SIZE];
+    for id in [SnakeId(0), SnakeId(1)] {
+        if !board.is_alive(&id) {
+            continue;
+        }
+        for cell in board.get_snake_body_iter(&id) {
+            blocked[cell_index::<B>(&board.position_from_native(cell))] = true;
+        }
+    }
+    blocked
+}
+
+fn neighbours<B: GameBoard>(position: &Position) -> Vec<Position> {
+    let size = B::SIZE as i32;
+    NEIGHBOURS.iter()
+        .map(|(dx, dy)| Position { x: position.x + dx, y: position.y + dy })
+        .filter(|next| next.x >= 0 && next.y >= 0 && next.x < size && next.y < size)
+        .collect()
+}
+
+fn cell_index<B: GameBoard>(position: &Position) -> usize {
+    (position.y * B::SIZE as i32 + position.x) as usize
+}
+
+
+#[cfg(test)]
+mod tests {
+    use battlesnake_game_types::types::SnakeId;
+    use battlesnake_game_types::wire_representation::Position;
+
+    use super::*;
+    use crate::game::{Board7x7, test_board};
+
+    // Snake 0 of length 3 and health 50 heading right at snake 1 of length 4 and health 80, two cells apart
+    fn facing_board() -> Board7x7 {
+        test_board(
+            r#"[{"x":3,"y":3},{"x":2,"y":3},{"x":1,"y":3}]"#, 50,
+            r#"[{"x":5,"y":3},{"x":5,"y":2},{"x":5,"y":1},{"x":5,"y":0}]"#, 80,
+            "[]",
+        )
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-6, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn food_distance_goes_around_the_bodies() {
+        // snake 0 is next to the food, snake 1 has to go around the head of snake 0
+        let board = test_board(
+            r#"[{"x":1,"y":3},{"x":1,"y":2},{"x":1,"y":1}]"#, 100,
+            r#"[{"x":5,"y":3},{"x":5,"y":2},{"x":5,"y":1}]"#, 100,
+            r#"[{"x":0,"y":3}]"#,
+        );
+        assert_eq!(food_distance(&board, SnakeId(0)), Some(1));
+        assert_eq!(food_distance(&board, SnakeId(1)), Some(7));
+        // (7 - 1) / 14, unreachable food counting as 14 moves
+        assert_close(features(&board, SnakeId(0))[3], 3.0 / 7.0);
+        assert_close(features(&board, SnakeId(1))[3], -3.0 / 7.0);
+
+        // the food in the corner is walled in by the body of snake 0
+        let board = test_board(
+            r#"[{"x":2,"y":0},{"x":1,"y":0},{"x":1,"y":1},{"x":0,"y":1}]"#, 100,
+            r#"[{"x":5,"y":3},{"x":5,"y":2},{"x":5,"y":1}]"#, 100,
+            r#"[{"x":0,"y":0}]"#,
+        );
+        assert_eq!(food_distance(&board, SnakeId(0)), None);
+        assert_eq!(food_distance(&board, SnakeId(1)), None);
+        assert_close(features(&board, SnakeId(0))[3], 0.0);
+    }
+
+    #[test]
+    fn free_neighbours_skip_walls_and_bodies() {
+        let board = facing_board();
+        assert_eq!(free_neighbours(&board, &Position { x: 3, y: 3 }), vec![Position { x: 3, y: 4 }, Position { x: 3, y: 2 }, Position { x: 4, y: 3 }]);
+        assert_eq!(free_neighbours(&board, &Position { x: 6, y: 0 }), vec![Position { x: 6, y: 1 }]);
+        assert_eq!(free_neighbours(&board, &Position { x: 4, y: 3 }).len(), 2);
+    }
+
+    #[test]
+    fn head_to_head_is_risky_for_the_shorter_snake() {
+        let board = facing_board();
+        let between = Position { x: 4, y: 3 };
+        assert!(head_to_head_risk(&board, SnakeId(0), SnakeId(1), &between));
+        assert!(!head_to_head_risk(&board, SnakeId(1), SnakeId(0), &between));
+        assert!(!head_to_head_risk(&board, SnakeId(0), SnakeId(1), &Position { x: 3, y: 4 }));
+        // one of the 3 free cells next to the head of snake 0
+        assert_close(head_to_head_danger(&board, SnakeId(0), SnakeId(1)), 1.0 / 3.0);
+        assert_close(head_to_head_danger(&board, SnakeId(1), SnakeId(0)), 0.0);
+        assert_close(features(&board, SnakeId(0))[4], -1.0 / 3.0);
+        assert_close(features(&board, SnakeId(1))[4], 1.0 / 3.0);
+
+        // snakes of the same length both risk the cell between them
+        let board = test_board(
+            r#"[{"x":3,"y":3},{"x":2,"y":3},{"x":1,"y":3}]"#, 50,
+            r#"[{"x":5,"y":3},{"x":5,"y":2},{"x":5,"y":1}]"#, 80,
+            "[]",
+        );
+        assert!(head_to_head_risk(&board, SnakeId(1), SnakeId(0), &between));
+        assert_close(features(&board, SnakeId(0))[4], 0.0);
+    }
+
+    #[test]
+    fn evaluate_is_the_weighted_mean_of_the_features() {
+        let board = facing_board();
+        let weights = |length_weight, health_weight, head_to_head_weight| HeuristicEvaluator {
+            length_weight,
+            health_weight,
+            area_weight: 0.0,
+            food_weight: 0.0,
+            head_to_head_weight,
+        };
+        // length (3 - 4) / 4, health (50 - 80) / 100, head to head -1/3
+        assert_close(weights(1.0, 0.0, 0.0).evaluate(&board, SnakeId(0)), -0.25);
+        assert_close(weights(0.0, 1.0, 0.0).evaluate(&board, SnakeId(0)), -0.3);
+        assert_close(weights(1.0, 1.0, 0.0).evaluate(&board, SnakeId(0)), -0.275);
+        assert_close(weights(1.0, 0.0, 2.0).evaluate(&board, SnakeId(0)), (-0.25 - 2.0 / 3.0) / 3.0);
+        assert_close(weights(1.0, 0.0, 2.0).evaluate(&board, SnakeId(1)), (0.25 + 2.0 / 3.0) / 3.0);
+        assert_close(weights(0.0, 0.0, 0.0).evaluate(&board, SnakeId(0)), 0.0);
+        // every feature is zero sum, so are the scores of both snakes
+        let evaluator = HeuristicEvaluator::default();
+        assert!(evaluator.evaluate(&board, SnakeId(0)) < 0.0);
+        assert_close(evaluator.evaluate(&board, SnakeId(0)), -evaluator.evaluate(&board, SnakeId(1)));
+    }
+
+    #[test]
+    fn contested_entrance_gives_its_pocket_to_nobody() {
+        // mirrored snakes, the column between them is only entered through the cell next to both heads
+        let board = test_board(
+            r#"[{"x":2,"y":6},{"x":2,"y":5},{"x":2,"y":4},{"x":2,"y":3}]"#, 100,
+            r#"[{"x":4,"y":6},{"x":4,"y":5},{"x":4,"y":4},{"x":4,"y":3}]"#, 100,
+            "[]",
+        );
+        let (own, opponent) = area_control(&board, SnakeId(0), SnakeId(1));
+        assert_eq!(own, opponent);
+        assert_eq!(area_control(&board, SnakeId(1), SnakeId(0)), (opponent, own));
+    }
+}
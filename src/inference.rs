@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Instant;
+
+use clap::ValueEnum;
+use rand::seq::SliceRandom;
+use tch::{Device, Kind, no_grad, TchError, Tensor};
+
+use crate::alpha_zero_model::AlphaZeroModel;
+use crate::canonical_board::CanonicalBoard;
+use crate::game::{BoardInit, CanCanonical, GameBoard};
+use crate::terminal::TerminalConfig;
+
+// Batch norm epsilon of tch's default BatchNormConfig
+const BN_EPS: f64 = 1e-5;
+
+// Convolutions and linear layers of NeuralNetwork, each with the batch norm following it
+const CONVS: [(&str, &str, i64); 4] = [("conv1", "bn1", 1), ("conv2", "bn2", 1), ("conv3", "bn3", 0), ("conv4", "bn4", 0)];
+const HIDDEN: [(&str, &str); 2] = [("fc1", "fc1_bn"), ("fc2", "fc2_bn")];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LinearPrecision {
+    #[default]
+    Float,
+    // fc1 and fc2 weights packed as fp16 for fbgemm, halving the memory read by the largest layers
+    Fp16,
+}
+
+impl LinearPrecision {
+    // Largest difference allowed between the predictions of the model and of its inference version
+    pub fn tolerance(&self) -> f32 {
+        match self {
+            LinearPrecision::Float => 1e-4,
+            LinearPrecision::Fp16 => 1e-2,
+        }
+    }
+}
+
+struct Linear {
+    weight: Tensor,
+    bias: Tensor,
+    packed_fp16: Option<Tensor>,
+}
+
+impl Linear {
+    fn forward(&self, xs: &Tensor) -> Tensor {
+        match &self.packed_fp16 {
+            Some(packed) => xs.fbgemm_linear_fp16_weight_fp32_activation(packed, &self.bias),
+            None => xs.linear(&self.weight, Some(&self.bias)),
+        }
+    }
+}
+
+
+// Inference only version of AlphaZeroModel for live play on CPU: batch norms folded into the weights of the layer before
+// them, dropout removed, no VarStore nor autograd. Predictions match the model in evaluation mode up to float rounding,
+// or fp16 rounding of fc1 and fc2 with LinearPrecision::Fp16
+pub struct InferenceModel {
+    convs: Vec<(Tensor, Tensor, i64)>,
+    hidden: Vec<Linear>,
+    fc_v: Linear,
+    fc_pi: Linear,
+    num_channels: i64,
+    board_size: i64,
+}
+
+impl InferenceModel {
+    pub fn from_model(model: &AlphaZeroModel, precision: LinearPrecision) -> Result<Self, TchError> {
+        let variables = model.named_variables().into_iter()
+            .map(|(name, var)| (name, var.detach().to_device(Device::Cpu)))
+            .collect::<HashMap<String, Tensor>>();
+        let var = |name: String| variables.get(&name).map(|var| var.shallow_clone())
+            .ok_or_else(|| TchError::FileFormat(format!("missing variable {}", name)));
+        let mut folded = HashMap::new();
+        no_grad(|| {
+            for (layer, bn) in CONVS.iter().map(|(layer, bn, _)| (*layer, *bn)).chain(HIDDEN) {
+                let scale = var(format!("{}.weight", bn))? / (var(format!("{}.running_var", bn))? + BN_EPS).sqrt();
+                let weight = var(format!("{}.weight", layer))?;
+                let shape = [vec![-1], vec![1; weight.dim() - 1]].concat();
+                folded.insert(format!("{}.weight", layer), weight * scale.view(shape.as_slice()));
+                let bias = (var(format!("{}.bias", layer))? - var(format!("{}.running_mean", bn))?) * &scale + var(format!("{}.bias", bn))?;
+                folded.insert(format!("{}.bias", layer), bias);
+            }
+            for head in ["fc_v", "fc_pi"] {
+                for kind in ["weight", "bias"] {
+                    folded.insert(format!("{}.{}", head, kind), var(format!("{}.{}", head, kind))?);
+                }
+            }
+            Ok::<(), TchError>(())
+        })?;
+        Self::from_folded(folded, precision)
+    }
+
+    // Folded weights written by save
+    pub fn load(path: &Path, precision: LinearPrecision) -> Result<Self, TchError> {
+        Self::from_folded(Tensor::read_safetensors(path)?.into_iter().collect(), precision)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), TchError> {
+        let mut tensors = vec![];
+        for ((weight, bias, _), (layer, _, _)) in self.convs.iter().zip(CONVS) {
+            tensors.push((format!("{}.weight", layer), weight.shallow_clone()));
+            tensors.push((format!("{}.bias", layer), bias.shallow_clone()));
+        }
+        let linears = self.hidden.iter().zip(HIDDEN.map(|(layer, _)| layer))
+            .chain([(&self.fc_v, "fc_v"), (&self.fc_pi, "fc_pi")]);
+        for (linear, layer) in linears {
+            tensors.push((format!("{}.weight", layer), linear.weight.shallow_clone()));
+            tensors.push((format!("{}.bias", layer), linear.bias.shallow_clone()));
+        }
+        Tensor::write_safetensors(&tensors, path)
+    }
+
+    fn from_folded(mut folded: HashMap<String, Tensor>, precision: LinearPrecision) -> Result<Self, TchError> {
+        let mut take = |name: String| folded.remove(&name).ok_or_else(|| TchError::FileFormat(format!("missing tensor {}", name)));
+        let mut convs = vec![];
+        for (layer, _, padding) in CONVS {
+            convs.push((take(format!("{}.weight", layer))?, take(format!("{}.bias", layer))?, padding));
+        }
+        let mut linear = |layer: &str, fp16: bool| -> Result<Linear, TchError> {
+            let weight = take(format!("{}.weight", layer))?;
+            let packed_fp16 = if fp16 { Some(weight.f_fbgemm_pack_gemm_matrix_fp16()?) } else { None };
+            Ok(Linear { weight, bias: take(format!("{}.bias", layer))?, packed_fp16 })
+        };
+        let fp16 = precision == LinearPrecision::Fp16;
+        let hidden = vec![linear("fc1", fp16)?, linear("fc2", fp16)?];
+        let fc_v = linear("fc_v", false)?;
+        let fc_pi = linear("fc_pi", false)?;
+
+        // the flattened convolution output is num_channels * (board_size - 4)^2
+        let num_channels = convs[0].0.size()[0];
+        let inner = ((hidden[0].weight.size()[1] / num_channels) as f64).sqrt() as i64;
+        Ok(InferenceModel {
+            convs,
+            hidden,
+            fc_v,
+            fc_pi,
+            num_channels,
+            board_size: inner + 4,
+        })
+    }
+
+    pub fn board_size(&self) -> i64 {
+        self.board_size
+    }
+
+    // Log policies and values of a batch of boards
+    pub fn forward(&self, input: &Tensor) -> (Tensor, Tensor) {
+        no_grad(|| {
+            let mut xs = input.view([-1, 1, self.board_size, self.board_size]);
+            for (weight, bias, padding) in &self.convs {
+                xs = xs.conv2d(weight, Some(bias), [1, 1], [*padding, *padding], [1, 1], 1).relu();
+            }
+            let inner = self.board_size - 4;
+            xs = xs.view([-1, self.num_channels * inner * inner]);
+            for linear in &self.hidden {
+                xs = linear.forward(&xs).relu();
+            }
+            let v = self.fc_v.forward(&xs).tanh();
+            let pi = self.fc_pi.forward(&xs).log_softmax(1, Kind::Float);
+            (pi, v)
+        })
+    }
+
+    pub fn predict<B: GameBoard>(&self, board: &CanonicalBoard<B>) -> ([f32; 4], f32) {
+        let (pi, v) = self.forward(&board.to_tensor());
+        let pi: Vec<f32> = pi.exp().view(-1).try_into().unwrap();
+        let mut actions: [f32; 4] = [0.0; 4];
+        actions.copy_from_slice(&pi);
+        (actions, v.try_into().unwrap())
+    }
+}
+
+
+// Latency of the model and of its inference version on positions of random games, and the largest differences of
+// their predictions, which must stay within the tolerance of the precision
+pub fn benchmark_inference<B: GameBoard>(model: &AlphaZeroModel, inference: &InferenceModel, precision: LinearPrecision, positions: usize, terminal: TerminalConfig) -> bool {
+    let boards = random_positions::<B>(positions, terminal);
+
+    let start = Instant::now();
+    let predictions = boards.iter().map(|board| model.predict(board)).collect::<Vec<_>>();
+    let model_time = start.elapsed();
+    let start = Instant::now();
+    let inference_predictions = boards.iter().map(|board| inference.predict(board)).collect::<Vec<_>>();
+    let inference_time = start.elapsed();
+
+    let (mut max_pi_diff, mut max_v_diff) = (0.0f32, 0.0f32);
+    for ((pi, v), (inference_pi, inference_v)) in predictions.iter().zip(&inference_predictions) {
+        max_pi_diff = pi.iter().zip(inference_pi).map(|(a, b)| (a - b).abs()).fold(max_pi_diff, f32::max);
+        max_v_diff = max_v_diff.max((v - inference_v).abs());
+    }
+    let per_position = |time: std::time::Duration| time.as_secs_f64() * 1e6 / boards.len().max(1) as f64;
+    println!("MODEL : {:.1} us/position ; INFERENCE ({:?}) : {:.1} us/position ; SPEEDUP : {:.2}x",
+             per_position(model_time), precision, per_position(inference_time), model_time.as_secs_f64() / inference_time.as_secs_f64());
+    let tolerance = precision.tolerance();
+    let within_tolerance = max_pi_diff <= tolerance && max_v_diff <= tolerance;
+    println!("MAX PI DIFF : {:.2e} ; MAX V DIFF : {:.2e} ; TOLERANCE : {:.0e} ; {}",
+             max_pi_diff, max_v_diff, tolerance, if within_tolerance { "OK" } else { "OUT OF TOLERANCE" });
+    within_tolerance
+}
+
+// Positions of random games with random valid moves
+fn random_positions<B: GameBoard>(positions: usize, terminal: TerminalConfig) -> Vec<CanonicalBoard<B>> {
+    let mut boards = vec![];
+    while boards.len() < positions {
+        let mut canonical_board = B::init_random_board().as_canonical(1, terminal);
+        while boards.len() < positions && canonical_board.get_game_ended(1).is_none() {
+            boards.push(canonical_board);
+            let valid_moves = canonical_board.get_valid_moves();
+            let actions = (0..4).filter(|a| valid_moves[*a]).collect::<Vec<usize>>();
+            let Some(action) = actions.choose(&mut rand::thread_rng()) else { break };
+            (canonical_board, _) = canonical_board.get_next_state(*action, false);
+        }
+    }
+    boards
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::alpha_zero_model::TrainConfig;
+    use crate::data_loader::DataLoader;
+    use crate::evaluator::uniform_policy;
+    use crate::game::Board7x7;
+    use crate::metrics::MetricsLogger;
+    use crate::optimizer::{Optimizer, OptimizerConfig};
+
+    // Small model trained for a few steps, so that its batch norm statistics are no longer the initial ones
+    fn trained_model(boards: &[CanonicalBoard<Board7x7>]) -> AlphaZeroModel {
+        let mut model = AlphaZeroModel::new(16, 7);
+        let samples = boards.iter().flat_map(|board| board.get_mirroring_and_rotation(&uniform_policy(board))).collect::<Vec<_>>();
+        let ids = (0..samples.len()).collect();
+        let loader = DataLoader::new(Arc::new(samples), ids, 32, 0);
+        let mut optimizer = Optimizer::new(&model, OptimizerConfig::new(0.01));
+        let config = TrainConfig { epochs: 2, batch_size: 32, early_stopping_patience: None, prefetch_batches: 0 };
+        model.train(&loader, &[], &mut optimizer, config, &mut MetricsLogger::disabled(), &mut StdRng::seed_from_u64(0));
+        model
+    }
+
+    #[test]
+    fn inference_model_predicts_as_the_model() {
+        let boards = random_positions::<Board7x7>(64, TerminalConfig::default());
+        let model = trained_model(&boards);
+        for precision in [LinearPrecision::Float, LinearPrecision::Fp16] {
+            let inference = InferenceModel::from_model(&model, precision).unwrap();
+            for board in &boards {
+                let ((pi, v), (inference_pi, inference_v)) = (model.predict(board), inference.predict(board));
+                for (a, b) in pi.into_iter().zip(inference_pi).chain([(v, inference_v)]) {
+                    assert!((a - b).abs() <= precision.tolerance(), "{:?}: {} and {} differ", precision, a, b);
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,13 @@
 
+use clap::builder::{PossibleValuesParser, TypedValueParser};
 use clap::Parser;
 
+use crate::evaluator::EvaluatorKind;
+use crate::examples_file::Compression;
+use crate::inference::LinearPrecision;
+use crate::rollout::RolloutPolicyKind;
+use crate::optimizer::{LrSchedule, OptimizerKind};
+
 pub mod game;
 pub mod alpha_zero_model;
 pub mod config;
@@ -12,6 +19,25 @@ pub mod utils;
 pub mod canonical_board;
 pub mod examples_handler;
 pub mod normal_mcts;
+pub mod metrics;
+pub mod outcome;
+pub mod stats;
+pub mod terminal;
+pub mod optimizer;
+pub mod run_state;
+pub mod data_loader;
+pub mod replay_buffer;
+pub mod examples_file;
+pub mod dataset;
+pub mod npy;
+pub mod examples_export;
+pub mod game_logs;
+pub mod bootstrap;
+pub mod inference;
+pub mod torchscript;
+pub mod evaluator;
+pub mod heuristics;
+pub mod rollout;
 
 
 #[derive(Parser, Debug, Clone, Default)]
@@ -71,6 +97,30 @@ pub struct Args {
     #[arg(long)]
     pub vs_normal_mcts: Option<usize>,
 
+    // Plays the model against the same MCTS with another evaluator, see evaluator.rs
+    #[arg(long, value_enum)]
+    pub vs_evaluator: Option<EvaluatorKind>,
+
+    // Rollouts per leaf of the rollout evaluator
+    #[arg(long, default_value_t = 8usize)]
+    pub rollout_evaluator_rollouts: usize,
+
+    // Move choice of the normal_mcts rollouts, see rollout.rs
+    #[arg(long, value_enum, default_value_t = RolloutPolicyKind::Safe)]
+    pub rollout_policy: RolloutPolicyKind,
+
+    // Health at or below which the safe rollout policy heads for the nearest food
+    #[arg(long, default_value_t = 30)]
+    pub rollout_hungry_health: u8,
+
+    // Probability of a random safe move of the epsilon greedy rollout policy
+    #[arg(long, default_value_t = 0.1_f32)]
+    pub rollout_epsilon: f32,
+
+    // Stop rollouts after this many turns and score them with the heuristic evaluator
+    #[arg(long)]
+    pub rollout_max_depth: Option<usize>,
+
     #[arg(long, default_value_t = 512i64)]
     pub num_channels: i64,
 
@@ -78,9 +128,186 @@ pub struct Args {
     // 100 - 80 => 3food/20round
     // 100 - 75 => 3.75food/20round
     // 100 - 70 => 4.5food/20round
+    // 0 disables the threshold and plays games to the real end
     #[arg(long, default_value_t = 75)]
     pub min_health_threshold: u8,
 
+    // Anneal min_health_threshold linearly towards this value over the iterations
+    #[arg(long)]
+    pub min_health_threshold_final: Option<u8>,
+
+    // Stop games after this many turns and score them with the heuristic evaluator
+    #[arg(long)]
+    pub max_turns: Option<u32>,
+
+    #[arg(long, default_value_t = 1.0_f32)]
+    pub eval_length_weight: f32,
+
+    #[arg(long, default_value_t = 0.5_f32)]
+    pub eval_health_weight: f32,
+
+    #[arg(long, default_value_t = 1.0_f32)]
+    pub eval_area_weight: f32,
+
+    #[arg(long, default_value_t = 0.0_f32)]
+    pub eval_food_weight: f32,
+
+    #[arg(long, default_value_t = 0.0_f32)]
+    pub eval_head_to_head_weight: f32,
+
+    // Also write training metrics as TensorBoard event files in <save_dir>/tensorboard
+    #[arg(long, default_value_t = false)]
+    pub tensorboard: bool,
+
+    // Value target given to both players when a game is drawn
+    #[arg(long, default_value_t = 0.0_f32)]
+    pub draw_value: f32,
+
+    // 7, 11 or 19
+    #[arg(long, default_value_t = 11, value_parser = PossibleValuesParser::new(["7", "11", "19"]).map(|size| size.parse::<usize>().unwrap()))]
+    pub board_size: usize,
+
+    // Continue the run saved in save_dir (run_state.json) instead of starting a new one. The self play, training and
+    // arena games of the interrupted iteration are replayed with the same random draws
+    #[arg(long, default_value_t = false)]
+    pub resume: bool,
+
+    // Seed of the training RNGs and of the self play and arena games, random when not given
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    #[arg(long, value_enum, default_value_t = OptimizerKind::Adam)]
+    pub optimizer: OptimizerKind,
+
+    // SGD only
+    #[arg(long, default_value_t = 0.9_f64)]
+    pub momentum: f64,
+
+    #[arg(long, default_value_t = 0.0_f64)]
+    pub weight_decay: f64,
+
+    // Learning rate schedule over all the training epochs of the run
+    #[arg(long, value_enum, default_value_t = LrSchedule::Constant)]
+    pub lr_schedule: LrSchedule,
+
+    // step schedule: epochs between two decays
+    #[arg(long, default_value_t = 10_u64)]
+    pub lr_step_size: u64,
+
+    // step schedule: decay factor
+    #[arg(long, default_value_t = 0.1_f64)]
+    pub lr_gamma: f64,
+
+    // cosine schedule: final learning rate
+    #[arg(long, default_value_t = 0.0_f64)]
+    pub min_learning_rate: f64,
+
+    // optimizer steps of linear warmup at the start of the run
+    #[arg(long, default_value_t = 0_u64)]
+    pub lr_warmup_steps: u64,
+
+    // Share of the positions held out to validate each epoch, the weights of the best epoch are kept. 0 disables it
+    #[arg(long, default_value_t = 0.1_f32)]
+    pub validation_split: f32,
+
+    // Stop training once the validation loss did not improve for this many epochs
+    #[arg(long)]
+    pub early_stopping_patience: Option<usize>,
+
+    // Batches built ahead of training on a separate thread, 0 builds them on the training thread
+    #[arg(long, default_value_t = 0_usize)]
+    pub prefetch_batches: usize,
+
+    // Share of the positions of an iteration kept per iteration of age, 1.0 trains on old iterations whole
+    #[arg(long, default_value_t = 1.0_f32)]
+    pub replay_recency_decay: f32,
+
+    // Max positions drawn from a single iteration
+    #[arg(long)]
+    pub replay_iteration_quota: Option<usize>,
+
+    // Exponent of the value error priority when drawing positions, 0 draws uniformly
+    #[arg(long, default_value_t = 0.0_f32)]
+    pub replay_value_priority: f32,
+
+    // Grow the window of iterations trained on as in KataGo, 0.75 is a good value.
+    // The window is still capped by num_iters_for_train_examples_history
+    #[arg(long)]
+    pub replay_window_alpha: Option<f32>,
+
+    #[arg(long, default_value_t = 0.4_f32)]
+    pub replay_window_beta: f32,
+
+    // Iterations always trained on whole before the window starts growing slower
+    #[arg(long, default_value_t = 4.0_f32)]
+    pub replay_window_min: f32,
+
+    // Compression of the saved examples files, uncompressed ones are memory mapped during training while zstd ones are
+    // decompressed into memory
+    #[arg(long, value_enum, default_value_t = Compression::None)]
+    pub examples_compression: Compression,
+
+    // Export all the examples of save_dir to boards/policies/values .npy files in this directory, or a .npz archive
+    #[arg(long)]
+    pub export_examples: Option<String>,
+
+    // Import a .npz archive or a directory of .npy files laid out as by --export-examples into save_dir
+    #[arg(long)]
+    pub import_examples: Option<String>,
+
+    // Generate the symmetries of imported samples, for datasets that do not have them
+    #[arg(long, default_value_t = false)]
+    pub import_augment: bool,
+
+    // Pretrain the model on the Battlesnake engine game logs of this directory before self play
+    #[arg(long)]
+    pub pretrain_logs: Option<String>,
+
+    #[arg(long, default_value_t = 10)]
+    pub pretrain_epochs: i32,
+
+    // Play this many games with the rollout MCTS of normal_mcts and save them as a new examples file of save_dir,
+    // to be trained on first with --load-examples
+    #[arg(long)]
+    pub bootstrap_games: Option<usize>,
+
+    // Rollout MCTS iterations per thread and per move of the bootstrap games
+    #[arg(long, default_value_t = 2000_usize)]
+    pub bootstrap_mcts_iterations: usize,
+
+    #[arg(long, default_value_t = 12_usize)]
+    pub bootstrap_threads: usize,
+
+    // Loss weights of the auxiliary heads predicting the turns until the end of the game, the final length difference
+    // and the opponent's policy. A head is only added to the network when its weight is positive
+    #[arg(long, default_value_t = 0.0_f64)]
+    pub aux_turns_weight: f64,
+
+    #[arg(long, default_value_t = 0.0_f64)]
+    pub aux_length_weight: f64,
+
+    #[arg(long, default_value_t = 0.0_f64)]
+    pub aux_opponent_policy_weight: f64,
+
+    // Write the loaded model as an inference only model with folded batch norms to this file
+    #[arg(long)]
+    pub export_inference: Option<String>,
+
+    // Compare the latency and the predictions of the loaded model and of its inference version on this many positions
+    #[arg(long)]
+    pub benchmark_inference: Option<usize>,
+
+    #[arg(long, value_enum, default_value_t = LinearPrecision::Float)]
+    pub inference_precision: LinearPrecision,
+
+    // Trace the loaded model as a TorchScript module to this file, loadable with tch::CModule or torch.jit.load
+    #[arg(long)]
+    pub export_torchscript: Option<String>,
+
+    // Play the arena modes with a TorchScript module written by --export-torchscript instead of a checkpoint
+    #[arg(long)]
+    pub torchscript_model: Option<String>,
+
 }
 
 
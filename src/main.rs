@@ -1,14 +1,27 @@
 use std::path::PathBuf;
+use std::rc::Rc;
 
 use clap::Parser;
 
-use battlesnake_alphazero::alpha_zero_model::AlphaZeroModel;
+use battlesnake_alphazero::alpha_zero_model::{AlphaZeroModel, SharedModel};
 use battlesnake_alphazero::arena::Arena;
 use battlesnake_alphazero::Args;
+use battlesnake_alphazero::bootstrap::{BootstrapConfig, generate_bootstrap_examples};
 use battlesnake_alphazero::coach::Coach;
+use battlesnake_alphazero::evaluator::{Evaluator, EvaluatorKind, RolloutEvaluator, UniformEvaluator};
+use battlesnake_alphazero::examples_export::{export_examples, import_examples};
+use battlesnake_alphazero::examples_handler::ExamplesHandler;
+use battlesnake_alphazero::game::{ArrayBoard, Board11x11, Board19x19, Board7x7, GameBoard};
+use battlesnake_alphazero::game_logs::load_game_logs;
+use battlesnake_alphazero::heuristics::HeuristicEvaluator;
+use battlesnake_alphazero::inference::{benchmark_inference, InferenceModel};
 use battlesnake_alphazero::mcts::MCTS;
+use battlesnake_alphazero::neural_network::AuxConfig;
+use battlesnake_alphazero::rollout::RolloutConfig;
+use battlesnake_alphazero::terminal::TerminalConfig;
+use battlesnake_alphazero::torchscript::TorchScriptModel;
 
-pub fn print_board(board: &[[f32; 11]; 11]) {
+pub fn print_board(board: &ArrayBoard) {
     for row in board.iter() {
         print!("|");
         for cell in row.iter() {
@@ -31,14 +44,101 @@ pub fn print_board(board: &[[f32; 11]; 11]) {
 
 
 
-fn main() {
-    let args = Args::parse();
-    let mut model = AlphaZeroModel::new(args.num_channels);
+// Model MCTS against the same search driven by another evaluator
+fn play_vs_evaluator<B: GameBoard, N: Evaluator<B> + Clone, E: Evaluator<B> + Clone>(model: &N, evaluator: E, args: &Args) {
+    let model_mcts = MCTS::<B, N>::new(model, args.c_puct, args.num_mcts_sims);
+    let evaluator_mcts = MCTS::<B, E>::new(&evaluator, args.c_puct, args.num_mcts_sims);
+    let mut arena = Arena::new(model_mcts, Some(evaluator_mcts), TerminalConfig::from_args(args));
+    let (model_wins, evaluator_wins, draws) = arena.play_games(args.arena_compare);
+    println!("Model Wins: {}, {:?} Evaluator Wins: {}, Draws: {}", model_wins, args.vs_evaluator.unwrap(), evaluator_wins, draws);
+}
+
+fn is_arena_mode(args: &Args) -> bool {
+    args.vs_model_path.is_some() || args.vs_evaluator.is_some() || args.vs_normal_mcts.is_some()
+}
+
+// Games of the arena mode of args, played by the MCTS of model
+fn play_arena<B: GameBoard, N: Evaluator<B> + Clone>(model: &N, args: &Args) {
+    if let Some(vs_model_path) = &args.vs_model_path {
+        let path = PathBuf::from(&vs_model_path);
+        let mut other_model = AlphaZeroModel::new(args.num_channels, B::SIZE as i64);
+        if path.exists() {
+            println!("load vs model from {}", path.display());
+            other_model.load_checkpoint(&path).unwrap();
+        } else {
+            println!("No model found at {}", path.display());
+        }
+        let model_mcts = MCTS::<B, N>::new(model, args.c_puct, args.num_mcts_sims);
+        let other_model_mcts = MCTS::<B>::new(&Rc::new(other_model), args.c_puct, args.num_mcts_sims);
+        let mut arena = Arena::new(model_mcts, Some(other_model_mcts), TerminalConfig::from_args(args));
+        let (model_wins, other_model_wins, draws) = arena.play_games(args.arena_compare);
+        println!("Model Wins: {}, Other Model Wins: {}, Draws: {}", model_wins, other_model_wins, draws);
+    }else if let Some(kind) = args.vs_evaluator {
+        match kind {
+            EvaluatorKind::Uniform => play_vs_evaluator::<B, _, _>(model, UniformEvaluator, args),
+            EvaluatorKind::Heuristic => play_vs_evaluator::<B, _, _>(model, HeuristicEvaluator::from_args(args), args),
+            EvaluatorKind::Rollout => play_vs_evaluator::<B, _, _>(model, RolloutEvaluator { rollouts: args.rollout_evaluator_rollouts, config: RolloutConfig::from_args(args) }, args),
+        }
+    }else if let Some(vs_normal_mcts) = &args.vs_normal_mcts{
+        let model_mcts = MCTS::<B, N>::new(model, args.c_puct, args.num_mcts_sims);
+        let mut arena = Arena::<B, N>::new(model_mcts, None, TerminalConfig::from_args(args));
+        let (model_wins, other_model_wins, draws) = arena.play_games_vs_normal_mcts(args.arena_compare, *vs_normal_mcts, RolloutConfig::from_args(args));
+        println!("Model Wins: {}, MCTS({}) Wins: {}, Draws: {}", model_wins, *vs_normal_mcts,other_model_wins, draws);
+    }
+}
+
+
+fn run<B: GameBoard>(args: Args) {
+    let mut model = AlphaZeroModel::with_aux(args.num_channels, B::SIZE as i64, AuxConfig::from_args(&args));
     let save_dir = PathBuf::from(&args.save_dir);
     if !save_dir.exists() {
         std::fs::create_dir_all(&save_dir).unwrap();
     }
 
+    if args.export_examples.is_some() || args.import_examples.is_some() {
+        // no examples file is ever removed here
+        let mut examples_handler = ExamplesHandler::new(args.save_dir.clone(), usize::MAX, B::SIZE, args.examples_compression);
+        if let Some(output) = &args.export_examples {
+            let count = export_examples(&examples_handler, B::SIZE, &PathBuf::from(output)).unwrap();
+            println!("Exported {} samples to {}", count, output);
+        }
+        if let Some(input) = &args.import_examples {
+            match import_examples(&PathBuf::from(input), &mut examples_handler, B::SIZE, args.import_augment) {
+                Ok(count) => println!("Imported {} samples from {}", count, input),
+                Err(e) => {
+                    eprintln!("Failed to import {}: {}", input, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        return;
+    }
+
+    if let Some(games) = args.bootstrap_games {
+        let config = BootstrapConfig::from_args(&args, games);
+        let samples = generate_bootstrap_examples::<B>(config, TerminalConfig::from_args(&args));
+        println!("Bootstrapped {} samples from {} games of MCTS({})", samples.len(), games, config.mcts_iterations);
+        let mut examples_handler = ExamplesHandler::new(args.save_dir.clone(), usize::MAX, B::SIZE, args.examples_compression);
+        examples_handler.save_example(samples, None).unwrap();
+        return;
+    }
+
+    if let Some(path) = &args.torchscript_model {
+        if !is_arena_mode(&args) {
+            println!("TorchScript models are inference only, use them with --vs-model-path, --vs-evaluator or --vs-normal-mcts");
+            return;
+        }
+        println!("load TorchScript model from {}", path);
+        let model = match TorchScriptModel::load(&PathBuf::from(path), B::SIZE as i64) {
+            Ok(model) => model,
+            Err(e) => {
+                eprintln!("Failed to load {}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        play_arena::<B, _>(&Rc::new(model), &args);
+        return;
+    }
     if args.load_model {
         let path = PathBuf::from(&args.save_dir).join("best.safetensors");
         if path.exists() {
@@ -50,29 +150,55 @@ fn main() {
     } else {
         println!("Not loading a checkpoint.");
     }
-    if let Some(vs_model_path) = &args.vs_model_path {
-        let path = PathBuf::from(&vs_model_path);
-        let mut other_model = AlphaZeroModel::new(args.num_channels);
-        if path.exists() {
-            println!("load vs model from {}", path.display());
-            other_model.load_checkpoint(&path).unwrap();
-        } else {
-            println!("No model found at {}", path.display());
+    if let Some(path) = &args.export_torchscript {
+        model.export_torchscript(&PathBuf::from(path)).unwrap();
+        println!("TorchScript model saved to {}", path);
+        return;
+    }
+    if args.export_inference.is_some() || args.benchmark_inference.is_some() {
+        let inference = InferenceModel::from_model(&model, args.inference_precision).unwrap();
+        if let Some(path) = &args.export_inference {
+            inference.save(&PathBuf::from(path)).unwrap();
+            println!("Inference model saved to {}", path);
         }
-        let model_mcts = MCTS::new(&model, args.c_puct, args.num_mcts_sims);
-        let other_model_mcts = MCTS::new(&other_model, args.c_puct, args.num_mcts_sims);
-        let mut arena = Arena::new(model_mcts, Some(other_model_mcts), args.min_health_threshold);
-        let (model_wins, other_model_wins, draws) = arena.play_games(args.arena_compare);
-        println!("Model Wins: {}, Other Model Wins: {}, Draws: {}", model_wins, other_model_wins, draws);
-    }else if let Some(vs_normal_mcts) = &args.vs_normal_mcts{
-        let model_mcts = MCTS::new(&model, args.c_puct, args.num_mcts_sims);
-        let mut arena = Arena::new(model_mcts, None, args.min_health_threshold);
-        let (model_wins, other_model_wins, draws) = arena.play_games_vs_normal_mcts(args.arena_compare, *vs_normal_mcts);
-        println!("Model Wins: {}, MCTS({}) Wins: {}, Draws: {}", model_wins, *vs_normal_mcts,other_model_wins, draws);
+        if let Some(positions) = args.benchmark_inference {
+            if !benchmark_inference::<B>(&model, &inference, args.inference_precision, positions, TerminalConfig::from_args(&args)) {
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if is_arena_mode(&args) {
+        play_arena::<B, SharedModel>(&Rc::new(model), &args);
     }
     else{
-        let mut coach = Coach::new(model, &args);
+        let mut coach = match Coach::<B>::new(model, &args) {
+            Ok(coach) => coach,
+            Err(e) => {
+                eprintln!("Failed to start training in {}: {}", args.save_dir, e);
+                std::process::exit(1);
+            }
+        };
+        if let Some(logs) = &args.pretrain_logs {
+            if coach.run_state.pretrained {
+                println!("Already pretrained, skipping the game logs");
+            } else {
+                let samples = load_game_logs::<B>(&PathBuf::from(logs), args.draw_value).unwrap();
+                coach.pretrain(samples, args.pretrain_epochs).unwrap();
+            }
+        }
         println!("Starting the learning process");
         coach.learn().unwrap();
     }
 }
+
+
+fn main() {
+    let args = Args::parse();
+    match args.board_size {
+        7 => run::<Board7x7>(args),
+        11 => run::<Board11x11>(args),
+        19 => run::<Board19x19>(args),
+        board_size => unreachable!("board size {} is rejected by the argument parser", board_size),
+    }
+}
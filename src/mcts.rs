@@ -1,70 +1,69 @@
 use std::collections::HashMap;
-use rand::distributions::Distribution;
+use std::marker::PhantomData;
 
 use rand::seq::SliceRandom;
-use rand_distr::Dirichlet;
 
-use crate::alpha_zero_model::AlphaZeroModel;
-use crate::canonical_board::CanonicalBoard;
+use crate::alpha_zero_model::SharedModel;
+use crate::canonical_board::{BoardKey, CanonicalBoard};
 use crate::config::{ACTION_SIZE, EPS};
+use crate::evaluator::Evaluator;
+use crate::game::GameBoard;
+use crate::utils::{entropy, with_game_rng};
 
 #[derive(Clone)]
-pub struct MCTS {
-    // Game should be a trait that your specific game implements
-    nnet: AlphaZeroModel,
-    // NeuralNet should be a trait for neural network implementations
-    qsa: HashMap<([u8;121], usize), f32>,
+pub struct MCTS<B: GameBoard, E: Evaluator<B> = SharedModel> {
+    evaluator: E,
+    // prior policies and values of the positions, the neural network or a stand-in for it
+    qsa: HashMap<(BoardKey, usize), f32>,
     // stores Q values for (state, action)
-    nsa: HashMap<([u8;121], usize), usize>,
+    nsa: HashMap<(BoardKey, usize), usize>,
     // stores visit counts for (state, action)
-    ns: HashMap<[u8;121], usize>,
+    ns: HashMap<BoardKey, usize>,
     // stores visit counts for states
-    ps: HashMap<[u8;121], [f32; 4]>,
+    ps: HashMap<BoardKey, [f32; 4]>,
     // initial policy from the neural network
-    es: HashMap<[u8;121], f32>,
+    es: HashMap<BoardKey, Option<f32>>,
     // game termination statuses
-    vs: HashMap<[u8;121], [bool; 4]>,
+    vs: HashMap<BoardKey, [bool; 4]>,
     // valid moves
+    opponent_priors: HashMap<BoardKey, ([f32; 4], f32)>,
+    // policy and value of the opponent's decisions, predicted from the first player's view
     c_puct: f32,
     num_mcts_sims: i32,
     pub max_deep: i32,
+    board: PhantomData<B>,
 }
 
-impl MCTS {
-    pub fn new(nnet: &AlphaZeroModel, c_puct:f32, num_mcts_sims:i32) -> Self {
+impl<B: GameBoard, E: Evaluator<B> + Clone> MCTS<B, E> {
+    pub fn new(evaluator: &E, c_puct:f32, num_mcts_sims:i32) -> Self {
         MCTS {
-            nnet: nnet.clone(),
+            evaluator: evaluator.clone(),
             qsa: HashMap::new(),
             nsa: HashMap::new(),
             ns: HashMap::new(),
             ps: HashMap::new(),
             es: HashMap::new(),
             vs: HashMap::new(),
+            opponent_priors: HashMap::new(),
             c_puct,
             num_mcts_sims,
-            max_deep:0
+            max_deep:0,
+            board: PhantomData,
         }
     }
 
-    pub fn get_action_prob(&mut self, state: &CanonicalBoard, temp: f32) -> [f32; 4] {
+    pub fn get_action_prob(&mut self, state: &CanonicalBoard<B>, temp: f32) -> [f32; 4] {
         let current_state = state.reset_and_clone_as_current_player();
         for _ in 0..self.num_mcts_sims {
             self.search(current_state,0);
         }
-        let s = current_state.to_hashmap_bytes();
-        let mut counts: [usize; 4] = [0; 4];
-        for (a, count) in counts.iter_mut().enumerate().take(ACTION_SIZE as usize) {
-            let key = (s, a);
-            if self.nsa.contains_key(&key) {
-                *count = self.nsa[&key];
-            }
-        }
+        let counts = self.get_visit_counts(&current_state);
         if temp == 0.0 {
             let max = counts.iter().max().unwrap();
             let best_actions: Vec<usize> = counts.iter().enumerate().filter(|(_, &count)| count == *max).map(|(i, _)| i).collect();
-            let best_action = best_actions.choose(&mut rand::thread_rng()).unwrap();
+            let best_action = with_game_rng(|rng| *best_actions.choose(rng).unwrap());
             let mut probabilities = [0.0; 4];
-            probabilities[*best_action] = 1.0;
+            probabilities[best_action] = 1.0;
             probabilities
         } else {
             let mut counts_float: [f32; 4] = [0.0; 4];
@@ -81,19 +80,61 @@ impl MCTS {
         }
     }
 
-    fn search(&mut self, state: CanonicalBoard, deep:i32) -> f32 {
+    fn get_visit_counts(&self, state: &CanonicalBoard<B>) -> [usize; 4] {
+        let s = state.to_hashmap_key();
+        let mut counts: [usize; 4] = [0; 4];
+        for (a, count) in counts.iter_mut().enumerate().take(ACTION_SIZE as usize) {
+            let key = (s, a);
+            if self.nsa.contains_key(&key) {
+                *count = self.nsa[&key];
+            }
+        }
+        counts
+    }
+
+    // Visit distribution at the root of the last search from state
+    pub fn get_visit_distribution(&self, state: &CanonicalBoard<B>) -> [f32; 4] {
+        let counts = self.get_visit_counts(&state.reset_and_clone_as_current_player());
+        let total: usize = counts.iter().sum();
+        counts.map(|count| if total == 0 { 0.0 } else { count as f32 / total as f32 })
+    }
+
+    // Entropy of the root visit distribution of the last search from state
+    pub fn get_policy_entropy(&self, state: &CanonicalBoard<B>) -> f32 {
+        let counts = self.get_visit_counts(&state.reset_and_clone_as_current_player());
+        let total: usize = counts.iter().sum();
+        if total == 0 {
+            return 0.0;
+        }
+        let probabilities = counts.map(|count| count as f32 / total as f32);
+        entropy(&probabilities)
+    }
+
+    fn search(&mut self, state: CanonicalBoard<B>, deep:i32) -> f32 {
         let main_player = state.first_player;
         if self.max_deep < deep{
             self.max_deep = deep;
         }
-        let s = state.to_hashmap_bytes();
+        let s = state.to_hashmap_key();
         let game_ended = self.es.entry(s).or_insert_with(|| state.get_game_ended(main_player));
-        if *game_ended != 0.0 {
-            return -*game_ended;
+        if let Some(value) = *game_ended {
+            return -value;
         }
 
         if let std::collections::hash_map::Entry::Vacant(e) = self.ps.entry(s) {
-            let (mut p, v) = self.nnet.predict(&state);
+            // the opponent decides on the board the first player just searched, it is expanded with the opponent
+            // policy head when the model has one so that both moves come from the same view
+            let opponent_prior = if state.prev_action.is_some() { self.opponent_priors.remove(&s) } else { None };
+            let (mut p, v) = match opponent_prior {
+                Some(prior) => prior,
+                None => {
+                    let (p, v, opponent_p) = self.evaluator.evaluate_with_opponent(&state);
+                    if let (Some(opponent_p), None) = (opponent_p, state.prev_action) {
+                        self.opponent_priors.insert(state.as_opponent().to_hashmap_key(), (opponent_p, -v));
+                    }
+                    (p, v)
+                }
+            };
             let valid_moves = state.get_valid_moves();
             p.iter_mut().enumerate().for_each(|(i, pi)| {
                 if !valid_moves[i] {
@@ -166,4 +207,47 @@ impl MCTS {
         *ns_entry += 1;
         -v
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use battlesnake_game_types::types::Move;
+
+    use super::MCTS;
+    use crate::evaluator::UniformEvaluator;
+    use crate::game::{test_board, Board7x7, CanCanonical};
+    use crate::terminal::TerminalConfig;
+
+    fn search(board: Board7x7) -> [f32; 4] {
+        let mut mcts = MCTS::<Board7x7, UniformEvaluator>::new(&UniformEvaluator, 4.0, 200);
+        mcts.get_action_prob(&board.as_canonical(1, TerminalConfig::default()), 1.0)
+    }
+
+    fn most_visited(probabilities: [f32; 4]) -> Move {
+        Move::from_index((0..4).max_by(|a, b| probabilities[*a].total_cmp(&probabilities[*b])).unwrap())
+    }
+
+    #[test]
+    fn only_move_out_of_a_corner_gets_all_the_visits() {
+        let board = test_board(
+            r#"[{"x":0,"y":6},{"x":1,"y":6},{"x":2,"y":6}]"#, 100,
+            r#"[{"x":6,"y":0},{"x":5,"y":0},{"x":4,"y":0}]"#, 100,
+            "[]",
+        );
+        let mut expected = [0.0; 4];
+        expected[Move::Down.as_index()] = 1.0;
+        assert_eq!(search(board), expected);
+    }
+
+    #[test]
+    fn starving_snake_eats_the_food_next_to_it_and_wins() {
+        // without the food both snakes starve this turn and the game is drawn
+        let board = test_board(
+            r#"[{"x":3,"y":3},{"x":3,"y":2},{"x":3,"y":1}]"#, 1,
+            r#"[{"x":0,"y":6},{"x":0,"y":5},{"x":0,"y":4}]"#, 1,
+            r#"[{"x":4,"y":3}]"#,
+        );
+        assert_eq!(most_visited(search(board)), Move::Right);
+    }
+}
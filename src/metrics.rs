@@ -0,0 +1,243 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{Map, Value};
+
+// Records training metrics as JSON lines and, optionally, TensorBoard event files
+pub struct MetricsLogger {
+    jsonl: Option<BufWriter<File>>,
+    tensorboard: Option<EventWriter>,
+    pub batch_step: u64,
+    pub epoch_step: u64,
+}
+
+impl MetricsLogger {
+    pub fn new(save_dir: &Path, tensorboard: bool) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(save_dir.join("metrics.jsonl"))?;
+        let tensorboard = if tensorboard {
+            Some(EventWriter::new(&save_dir.join("tensorboard"))?)
+        } else {
+            None
+        };
+        Ok(MetricsLogger {
+            jsonl: Some(BufWriter::new(file)),
+            tensorboard,
+            batch_step: 0,
+            epoch_step: 0,
+        })
+    }
+
+    // Logger that drops everything
+    pub fn disabled() -> Self {
+        MetricsLogger {
+            jsonl: None,
+            tensorboard: None,
+            batch_step: 0,
+            epoch_step: 0,
+        }
+    }
+
+    // kind is "batch", "epoch", "arena"...; values are written as kind/name in TensorBoard
+    pub fn log(&mut self, kind: &str, step: u64, values: &[(&str, f64)]) {
+        if let Some(jsonl) = &mut self.jsonl {
+            let mut line = Map::new();
+            line.insert("kind".to_string(), Value::from(kind));
+            line.insert("step".to_string(), Value::from(step));
+            line.insert("time".to_string(), Value::from(wall_time()));
+            for (name, value) in values {
+                line.insert(name.to_string(), Value::from(*value));
+            }
+            writeln!(jsonl, "{}", Value::Object(line)).unwrap_or_else(|e| {
+                println!("Failed to write metrics: {}", e);
+            });
+        }
+        if let Some(tensorboard) = &mut self.tensorboard {
+            let tagged = values.iter().map(|(name, value)| (format!("{}/{}", kind, name), *value as f32)).collect::<Vec<_>>();
+            tensorboard.write_scalars(step as i64, &tagged).unwrap_or_else(|e| {
+                println!("Failed to write tensorboard event: {}", e);
+            });
+        }
+    }
+
+    pub fn log_batch(&mut self, values: &[(&str, f64)]) {
+        self.batch_step += 1;
+        self.log("batch", self.batch_step, values);
+    }
+
+    pub fn log_epoch(&mut self, values: &[(&str, f64)]) {
+        self.epoch_step += 1;
+        self.log("epoch", self.epoch_step, values);
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(jsonl) = &mut self.jsonl {
+            let _ = jsonl.flush();
+        }
+        if let Some(tensorboard) = &mut self.tensorboard {
+            let _ = tensorboard.file.flush();
+        }
+    }
+}
+
+impl Drop for MetricsLogger {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+
+fn wall_time() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+
+// Minimal TensorBoard event file writer (TFRecord framing of Event protos holding scalar summaries)
+pub struct EventWriter {
+    file: BufWriter<File>,
+}
+
+impl EventWriter {
+    pub fn new(dir: &Path) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let file_name = format!("events.out.tfevents.{}.battlesnake_alphazero", wall_time() as u64);
+        let mut writer = EventWriter {
+            file: BufWriter::new(File::create(dir.join(file_name))?),
+        };
+        // first event of a file only carries the format version
+        let mut event = vec![];
+        encode_double(&mut event, 1, wall_time());
+        encode_bytes(&mut event, 3, b"brain.Event:2");
+        writer.write_record(&event)?;
+        Ok(writer)
+    }
+
+    pub fn write_scalars(&mut self, step: i64, scalars: &[(String, f32)]) -> std::io::Result<()> {
+        let mut summary = vec![];
+        for (tag, value) in scalars {
+            let mut summary_value = vec![];
+            encode_bytes(&mut summary_value, 1, tag.as_bytes());
+            encode_float(&mut summary_value, 2, *value);
+            encode_bytes(&mut summary, 1, &summary_value);
+        }
+        let mut event = vec![];
+        encode_double(&mut event, 1, wall_time());
+        encode_varint_field(&mut event, 2, step as u64);
+        encode_bytes(&mut event, 5, &summary);
+        self.write_record(&event)
+    }
+
+    fn write_record(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let len = (data.len() as u64).to_le_bytes();
+        self.file.write_all(&len)?;
+        self.file.write_all(&masked_crc32c(&len).to_le_bytes())?;
+        self.file.write_all(data)?;
+        self.file.write_all(&masked_crc32c(data).to_le_bytes())
+    }
+}
+
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn encode_varint_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    encode_varint(buf, (field << 3) as u64);
+    encode_varint(buf, value);
+}
+
+fn encode_double(buf: &mut Vec<u8>, field: u32, value: f64) {
+    encode_varint(buf, ((field << 3) | 1) as u64);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn encode_float(buf: &mut Vec<u8>, field: u32, value: f32) {
+    encode_varint(buf, ((field << 3) | 5) as u64);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn encode_bytes(buf: &mut Vec<u8>, field: u32, value: &[u8]) {
+    encode_varint(buf, ((field << 3) | 2) as u64);
+    encode_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0x82F6_3B78 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn masked_crc32c(data: &[u8]) -> u32 {
+    (crc32c(data).rotate_right(15)).wrapping_add(0xa282_ead8)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    #[test]
+    fn crc32c_matches_known_vectors() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(&[0; 32]), 0x8a91_36aa);
+        assert_eq!(masked_crc32c(b"123456789"), 0xc78a_b0e5);
+        assert_eq!(masked_crc32c(&[0; 8]), 0x0798_0329);
+    }
+
+    #[test]
+    fn varints_use_seven_bits_per_byte() {
+        let mut buf = vec![];
+        encode_varint(&mut buf, 1);
+        encode_varint(&mut buf, 300);
+        encode_varint(&mut buf, u32::MAX as u64);
+        assert_eq!(buf, [0x01, 0xac, 0x02, 0xff, 0xff, 0xff, 0xff, 0x0f]);
+    }
+
+    // Data of the TFRecords of a file, checking the length and the data CRCs of each
+    fn read_records(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut records = vec![];
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let (len, len_crc) = (&rest[..8], u32::from_le_bytes(rest[8..12].try_into().unwrap()));
+            assert_eq!(len_crc, masked_crc32c(len));
+            let len = u64::from_le_bytes(len.try_into().unwrap()) as usize;
+            let (data, data_crc) = (&rest[12..12 + len], u32::from_le_bytes(rest[12 + len..16 + len].try_into().unwrap()));
+            assert_eq!(data_crc, masked_crc32c(data));
+            records.push(data.to_vec());
+            rest = &rest[16 + len..];
+        }
+        records
+    }
+
+    #[test]
+    fn event_file_holds_framed_version_and_scalar_events() {
+        let dir = test_dir("event_file");
+        let mut writer = EventWriter::new(&dir).unwrap();
+        writer.write_scalars(300, &[("a".to_string(), 1.0)]).unwrap();
+        writer.file.flush().unwrap();
+        let path = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let records = read_records(&std::fs::read(path).unwrap());
+        assert_eq!(records.len(), 2);
+
+        // wall_time double, then file_version
+        assert_eq!(records[0][0], 0x09);
+        assert_eq!(&records[0][9..], [&[0x1a, 13][..], b"brain.Event:2"].concat());
+
+        // wall_time double, step varint, then a summary of one value with its tag and simple_value
+        let event = &records[1];
+        assert_eq!(event[0], 0x09);
+        assert_eq!(&event[9..], [0x10, 0xac, 0x02, 0x2a, 10, 0x0a, 8, 0x0a, 1, b'a', 0x15, 0x00, 0x00, 0x80, 0x3f]);
+    }
+}
@@ -1,20 +1,58 @@
-use tch::nn;
+use tch::{nn, Tensor};
 use tch::nn::{ConvConfig, Module, ModuleT};
 
-use crate::config::{ACTION_SIZE, BOARD_SIZE, DROPOUT};
+use crate::Args;
+use crate::config::{ACTION_SIZE, DROPOUT};
+
+// Loss weights of the auxiliary heads, a head only exists when its weight is positive
+// so that checkpoints without it still load
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AuxConfig {
+    // turns until the end of the game
+    pub turns_weight: f64,
+    // final length difference with the opponent
+    pub length_weight: f64,
+    // opponent's policy at the same turn
+    pub opponent_policy_weight: f64,
+}
+
+impl AuxConfig {
+    pub fn from_args(args: &Args) -> Self {
+        AuxConfig {
+            turns_weight: args.aux_turns_weight,
+            length_weight: args.aux_length_weight,
+            opponent_policy_weight: args.aux_opponent_policy_weight,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.turns_weight > 0.0 || self.length_weight > 0.0 || self.opponent_policy_weight > 0.0
+    }
+}
+
+// Outputs of the auxiliary heads that exist: scaled turns and length difference of shape [N, 1],
+// opponent log policy of shape [N, 4]
+pub struct AuxOutputs {
+    pub turns: Option<Tensor>,
+    pub length: Option<Tensor>,
+    pub opponent_pi: Option<Tensor>,
+}
 
 pub struct NeuralNetwork {
     seq: nn::SequentialT,
     fc_v: nn::Linear,
     fc_pi: nn::Linear,
+    fc_turns: Option<nn::Linear>,
+    fc_length: Option<nn::Linear>,
+    fc_opponent_pi: Option<nn::Linear>,
 }
 
 impl NeuralNetwork {
-    pub(crate) fn new(vs: &nn::Path, num_channels: i64) -> NeuralNetwork {
+    pub(crate) fn new(vs: &nn::Path, num_channels: i64, board_size: i64, aux: AuxConfig) -> NeuralNetwork {
         let stride = ConvConfig { stride: 1, ..Default::default() };
         let stride_padding = ConvConfig { stride: 1, padding: 1, ..Default::default() };
         let seq = nn::seq_t()
-            .add_fn(move |xs| xs.view([-1, 1, BOARD_SIZE, BOARD_SIZE]))
+            .add_fn(move |xs| xs.view([-1, 1, board_size, board_size]))
             .add(nn::conv2d(vs / "conv1", 1, num_channels, 3, stride_padding))
             .add(nn::batch_norm2d(vs / "bn1", num_channels, Default::default()))
             .add_fn(|xs| xs.relu())
@@ -27,8 +65,8 @@ impl NeuralNetwork {
             .add(nn::conv2d(vs / "conv4", num_channels, num_channels, 3, stride))
             .add(nn::batch_norm2d(vs / "bn4", num_channels, Default::default()))
             .add_fn(|xs| xs.relu())
-            .add_fn(move |xs| xs.view([-1, num_channels * (BOARD_SIZE - 4) * (BOARD_SIZE - 4)]))
-            .add(nn::linear(vs / "fc1", num_channels * (BOARD_SIZE - 4) * (BOARD_SIZE - 4), 1024, Default::default()))
+            .add_fn(move |xs| xs.view([-1, num_channels * (board_size - 4) * (board_size - 4)]))
+            .add(nn::linear(vs / "fc1", num_channels * (board_size - 4) * (board_size - 4), 1024, Default::default()))
             .add(nn::batch_norm1d(vs / "fc1_bn", 1024, Default::default()))
             .add_fn(|xs| xs.relu())
             .add_fn_t(|xs, train| xs.dropout(DROPOUT, train))
@@ -38,19 +76,41 @@ impl NeuralNetwork {
             .add_fn_t(|xs, train| xs.dropout(DROPOUT, train));
         let fc_v = nn::linear(vs / "fc_v", 512, 1, Default::default());
         let fc_pi = nn::linear(vs / "fc_pi", 512, ACTION_SIZE, Default::default());
+        let fc_turns = (aux.turns_weight > 0.0).then(|| nn::linear(vs / "fc_turns", 512, 1, Default::default()));
+        let fc_length = (aux.length_weight > 0.0).then(|| nn::linear(vs / "fc_length", 512, 1, Default::default()));
+        let fc_opponent_pi = (aux.opponent_policy_weight > 0.0).then(|| nn::linear(vs / "fc_opponent_pi", 512, ACTION_SIZE, Default::default()));
 
 
         NeuralNetwork {
             seq,
             fc_v,
             fc_pi,
+            fc_turns,
+            fc_length,
+            fc_opponent_pi,
         }
     }
 
     pub fn forward(&self, input: &tch::Tensor, is_training: bool) -> (tch::Tensor, tch::Tensor) {
         let x = self.seq.forward_t(input, is_training);
-        let v = self.fc_v.forward(&x).tanh();
-        let pi = self.fc_pi.forward(&x).log_softmax(1, tch::Kind::Float);
+        self.forward_heads(&x)
+    }
+
+    // Policy and value along with the auxiliary heads
+    pub fn forward_aux(&self, input: &tch::Tensor, is_training: bool) -> (tch::Tensor, tch::Tensor, AuxOutputs) {
+        let x = self.seq.forward_t(input, is_training);
+        let (pi, v) = self.forward_heads(&x);
+        let aux = AuxOutputs {
+            turns: self.fc_turns.as_ref().map(|fc| fc.forward(&x)),
+            length: self.fc_length.as_ref().map(|fc| fc.forward(&x)),
+            opponent_pi: self.fc_opponent_pi.as_ref().map(|fc| fc.forward(&x).log_softmax(1, tch::Kind::Float)),
+        };
+        (pi, v, aux)
+    }
+
+    fn forward_heads(&self, x: &tch::Tensor) -> (tch::Tensor, tch::Tensor) {
+        let v = self.fc_v.forward(x).tanh();
+        let pi = self.fc_pi.forward(x).log_softmax(1, tch::Kind::Float);
         (pi, v)
     }
 }
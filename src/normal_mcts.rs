@@ -1,176 +1,188 @@
-use battlesnake_game_types::compact_representation::StandardCellBoard4Snakes11x11;
-use battlesnake_game_types::types::{Move, SnakeId, VictorDeterminableGame};
-use rand::prelude::IteratorRandom;
+use battlesnake_game_types::types::{Move, SnakeId};
+use rand::seq::SliceRandom;
 use rayon::iter::IntoParallelIterator;
-use crate::game::MoveBattleSnake;
+use crate::game::{GameBoard, MoveBattleSnake};
+use crate::rollout::RolloutConfig;
 use rayon::iter::ParallelIterator;
 
-pub fn run_simulation(state: StandardCellBoard4Snakes11x11) -> Option<SnakeId> {
-    let mut current_state = state;
-    let mut rng = rand::thread_rng();
-    while !current_state.is_over() {
-        let chosen_moves = current_state.get_available_moves()
-            .into_iter()
-            .choose(&mut rng).unwrap();
-        current_state = current_state.simulate_moves(&chosen_moves, true);
-    }
-    current_state.get_winner()
+// Decoupled UCT for the simultaneous moves of both snakes: at each node every snake picks its own move with UCB1 over
+// its marginal statistics, the joint move leads to the child, and the rollout result updates the statistics of the
+// move each snake chose
+
+// Visits and summed rollout results in [0, 1] of a move of one snake at one node
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MoveStats {
+    pub visits: usize,
+    pub wins: f32,
 }
-pub fn backpropagate(tree: &mut [MCTSNode], mut node_index: usize, winner: Option<SnakeId>, player_id: &SnakeId) {
-    while let Some(node) = tree.get_mut(node_index) {
-        node.visits += 1;
-        if let Some(winner) = winner {
-            if winner == *player_id {
-                node.wins += 1;
-            }
-        }
-        match node.parent {
-            Some(parent) => {
-                if parent == node_index { // Check for self-reference
-                    break;
-                }
-                node_index = parent;
-            }
-            None => break,
-        };
+
+impl MoveStats {
+    pub fn win_rate(&self) -> f32 {
+        if self.visits == 0 { 0.5 } else { self.wins / self.visits as f32 }
     }
 }
 
-pub fn best_child(node: &MCTSNode, tree: &[MCTSNode], is_exploration: bool) -> usize {
-    let mut best_score = f64::MIN;
-    let mut best_child = 0;
-
-    for &child_index in &node.children {
-        let child = &tree[child_index];
-        let child_visits = if child.visits == 0 { 1 } else { child.visits } as f64;
-        let win_rate = child.wins as f64 / child_visits;
-        let exploration_value = if is_exploration {
-            (2.0 * (node.visits as f64).ln() / child_visits).sqrt()
-        } else {
-            0.0
-        };
-        let score = win_rate + exploration_value;
-
-        if score > best_score {
-            best_score = score;
-            best_child = child_index;
+// Statistics of each snake (by SnakeId) for each of its moves (by move index)
+pub type MarginalStats = [[MoveStats; 4]; 2];
+
+pub fn backpropagate<B: GameBoard>(tree: &mut [MCTSNode<B>], mut node_index: usize, values: [f32; 2]) {
+    while let Some(parent) = tree[node_index].parent {
+        let moves = tree[node_index].moves_made.unwrap();
+        let parent_node = &mut tree[parent];
+        parent_node.visits += 1;
+        for (idx, mv) in moves.iter().enumerate() {
+            let stats = &mut parent_node.stats[idx][mv.as_index()];
+            stats.visits += 1;
+            // a draw counts as half a win
+            stats.wins += (values[idx] + 1.0) / 2.0;
         }
+        node_index = parent;
     }
+}
 
-    best_child
+// UCB1 choice of snake idx among its moves at node, trying every move once first
+pub fn select_move<B: GameBoard>(node: &MCTSNode<B>, idx: usize, moves: &[Move]) -> Move {
+    let unvisited = moves.iter().filter(|mv| node.stats[idx][mv.as_index()].visits == 0).copied().collect::<Vec<Move>>();
+    if let Some(mv) = unvisited.choose(&mut rand::thread_rng()) {
+        return *mv;
+    }
+    let log_visits = (node.visits as f64).ln();
+    let score = |mv: &Move| {
+        let stats = node.stats[idx][mv.as_index()];
+        stats.win_rate() as f64 + (2.0 * log_visits / stats.visits as f64).sqrt()
+    };
+    *moves.iter().max_by(|a, b| score(a).partial_cmp(&score(b)).unwrap()).unwrap()
 }
 
-pub fn mcts(root: MCTSNode, player_id: &SnakeId, iterations: usize) -> Vec<MCTSNode> {
-    let mut tree = vec![root.clone()];
+pub fn mcts<B: GameBoard>(state: B, iterations: usize, rollout: &RolloutConfig) -> Vec<MCTSNode<B>> {
+    let mut tree = vec![MCTSNode::new(state, None, None, 0)];
     for _ in 0..iterations {
-        let mut node_index = 0; // Start from the root
-        while !tree[node_index].children.is_empty() && !tree[node_index].state.is_over() {
-            node_index = best_child(&tree[node_index], &tree, true);
-        }
-
-        // Expansion
-        if !tree[node_index].state.is_over() {
-            let tree_len = tree.len();
-            tree[node_index]
-                .state
-                .get_available_moves()
-                .into_iter()
-                .enumerate()
-                .for_each(|(child_index, node_moves)| {
-                    tree[node_index].children.push(tree_len + child_index); // Use the precomputed index here
-                    let node_moves_2 = node_moves.into_iter().enumerate().map(|(idx, mv)| (SnakeId(idx as u8), mv)).collect();
-                    tree.push(MCTSNode::new(tree[node_index].state.simulate_moves(&node_moves, true), Some(node_index), Some(node_moves_2), tree[node_index].deep + 1));
-                });
-        }
-
-        if !tree[node_index].children.is_empty() {
-            node_index = *tree[node_index].children.first().unwrap();
+        // Selection, down to a terminal node or a joint move never played from its node
+        let mut node_index = 0;
+        while !tree[node_index].state.is_over() {
+            let moves = tree[node_index].state.get_moves_for_each_snake();
+            let joint_move = [0, 1].map(|idx| select_move(&tree[node_index], idx, &moves[idx]));
+            match tree[node_index].children.iter().find(|(moves_made, _)| *moves_made == joint_move) {
+                Some((_, child_index)) => node_index = *child_index,
+                None => {
+                    // Expansion
+                    let child = MCTSNode::new(tree[node_index].state.simulate_moves(&joint_move, true), Some(node_index), Some(joint_move), tree[node_index].deep + 1);
+                    let child_index = tree.len();
+                    tree.push(child);
+                    tree[node_index].children.push((joint_move, child_index));
+                    node_index = child_index;
+                    break;
+                }
+            }
         }
 
-        // Simulation
-        let winner = run_simulation(tree[node_index].state);
+        // Simulation, the rollout result of one snake is the opposite of the other one
+        let value = rollout.run(tree[node_index].state, &SnakeId(0));
 
         // Backpropagation
-        backpropagate(&mut tree, node_index, winner, player_id);
+        backpropagate(&mut tree, node_index, [value, -value]);
     }
-
-    // Return the most visited move from the root
-    //let (deep, index) = get_deepest_level_and_index(&tree);
-    //println!("Deep: {}, Index: {}, nodes: {}", deep, index, tree.len());
     tree
 }
 
-pub fn best_node_from_mcts(tree:&[MCTSNode]) -> MCTSNode {
-    let best_move_index = best_child(&tree[0], tree, false);
-    tree[best_move_index].clone()
-}
-
-
-
-pub fn mcts_parallel(root: MCTSNode, player_id: &SnakeId, iterations: usize, num_threads: usize) -> MCTSNode {
-    let total_iterations = iterations * num_threads;
-    let per_thread_iterations = total_iterations / num_threads;
-
-    // Using Rayon to parallelize the MCTS execution across multiple threads
-    let trees: Vec<Vec<MCTSNode>> = (0..num_threads)
+// Root statistics of num_threads independent searches of iterations each, summed move by move for each snake
+pub fn mcts_parallel_root<B: GameBoard>(state: B, iterations: usize, num_threads: usize, rollout: &RolloutConfig) -> MarginalStats {
+    let roots: Vec<MarginalStats> = (0..num_threads)
         .into_par_iter()
-        .map(|_| {
-            let local_root = root.clone();
-            let local_player_id = *player_id;
-            let tree = mcts(local_root, &local_player_id, per_thread_iterations);
-            let root = tree[0].clone();
-            let relevant_indexes:Vec<_> = vec![0].into_iter().chain(root.children).collect();
-            relevant_indexes.iter().map(|&child_index| tree[child_index].clone()).collect()
-        })
+        .map(|_| mcts(state, iterations, rollout)[0].stats)
         .collect();
-    let mut result_tree = trees[0].clone();
-    let children_index = result_tree[0].children.clone();
-    for child_index in children_index{
-        let child_node = &mut result_tree[child_index];
-        for other_tree in trees.iter().take(num_threads).skip(1) {
-            for j in 0..other_tree[0].children.len() {
-                let other_child_index = other_tree[0].children[j];
-                let other_child_node = &other_tree[other_child_index];
-                if child_node.moves_made == other_child_node.moves_made {
-                    child_node.visits += other_child_node.visits;
-                    child_node.wins += other_child_node.wins;
-                }
+    let mut merged = MarginalStats::default();
+    for root in roots {
+        for (merged_moves, moves) in merged.iter_mut().zip(root) {
+            for (merged_stats, stats) in merged_moves.iter_mut().zip(moves) {
+                merged_stats.visits += stats.visits;
+                merged_stats.wins += stats.wins;
             }
-
         }
     }
-    best_node_from_mcts(&result_tree)
+    merged
+}
+
+// Most visited move of player_id after a root parallel search
+pub fn mcts_parallel<B: GameBoard>(state: B, player_id: &SnakeId, iterations: usize, num_threads: usize, rollout: &RolloutConfig) -> Move {
+    best_move(&mcts_parallel_root(state, iterations, num_threads, rollout), player_id)
+}
+
+pub fn best_move(stats: &MarginalStats, player_id: &SnakeId) -> Move {
+    let moves = &stats[player_id.0 as usize];
+    let best_index = (0..4).max_by(|a, b| {
+        (moves[*a].visits, moves[*a].win_rate()).partial_cmp(&(moves[*b].visits, moves[*b].win_rate())).unwrap()
+    }).unwrap();
+    Move::from_index(best_index)
+}
+
+// Visit distribution of the moves of player_id at the root and its rollout win rate
+pub fn root_policy_and_win_rate(stats: &MarginalStats, player_id: &SnakeId) -> ([f32; 4], f32) {
+    let moves = &stats[player_id.0 as usize];
+    let total = moves.iter().map(|stats| stats.visits).sum::<usize>();
+    if total == 0 {
+        return ([0.0; 4], 0.5);
+    }
+    let wins = moves.iter().map(|stats| stats.wins).sum::<f32>();
+    (moves.map(|stats| stats.visits as f32 / total as f32), wins / total as f32)
 }
 
 
 
 #[derive(Debug, Clone)]
-pub struct MCTSNode {
-    pub state: StandardCellBoard4Snakes11x11,
+pub struct MCTSNode<B: GameBoard> {
+    pub state: B,
     pub parent: Option<usize>,
 
-    pub children: Vec<usize>,
-    pub moves_made: Option<Vec<(SnakeId, Move)>>,
-    pub wins: usize,
+    // joint move played from this node and the index of the node it leads to
+    pub children: Vec<([Move; 2], usize)>,
+    // joint move that led to this node, by SnakeId
+    pub moves_made: Option<[Move; 2]>,
+    pub stats: MarginalStats,
     pub visits: usize,
     pub deep: usize,
 }
 
 
-impl MCTSNode {
-    pub fn new(state: StandardCellBoard4Snakes11x11, parent: Option<usize>, moves_made: Option<Vec<(SnakeId, Move)>>, deep: usize) -> MCTSNode {
+impl<B: GameBoard> MCTSNode<B> {
+    pub fn new(state: B, parent: Option<usize>, moves_made: Option<[Move; 2]>, deep: usize) -> MCTSNode<B> {
         MCTSNode {
             state,
             parent,
             moves_made,
             children: Vec::new(),
-            wins: 0,
+            stats: MarginalStats::default(),
             visits: 0,
             deep,
         }
     }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use battlesnake_game_types::types::{Move, SnakeId};
+
+    use super::mcts_parallel;
+    use crate::game::test_board;
+    use crate::rollout::RolloutConfig;
+
+    const FAR_OPPONENT: &str = r#"[{"x":6,"y":0},{"x":5,"y":0},{"x":4,"y":0}]"#;
+
+    #[test]
+    fn starving_snake_eats_the_food_next_to_it() {
+        let board = test_board(r#"[{"x":3,"y":3},{"x":3,"y":2},{"x":3,"y":1}]"#, 1, FAR_OPPONENT, 100, r#"[{"x":4,"y":3}]"#);
+        assert_eq!(mcts_parallel(board, &SnakeId(0), 500, 4, &RolloutConfig::default()), Move::Right);
+    }
 
-    pub fn get_best_move_made(&self, snake_id:&SnakeId) -> Move {
-        self.moves_made.as_ref().unwrap().iter().find(|(id, _)| id == snake_id).unwrap().1
+    #[test]
+    fn snake_does_not_walk_into_a_dead_end() {
+        // Left leads to the corner, walled in by the body
+        let board = test_board(
+            r#"[{"x":1,"y":6},{"x":1,"y":5},{"x":0,"y":5},{"x":0,"y":4},{"x":0,"y":3}]"#, 100,
+            FAR_OPPONENT, 100,
+            "[]",
+        );
+        assert_eq!(mcts_parallel(board, &SnakeId(0), 500, 4, &RolloutConfig::default()), Move::Right);
     }
 }
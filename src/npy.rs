@@ -0,0 +1,95 @@
+use std::io::{Error, ErrorKind, Read, Write};
+
+// Minimal reader and writer of NumPy .npy arrays of f32, C order
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+pub fn write_npy(writer: &mut impl Write, shape: &[usize], data: &[f32]) -> std::io::Result<()> {
+    let shape_str = match shape {
+        [len] => format!("({},)", len),
+        _ => format!("({})", shape.iter().map(|d| d.to_string()).collect::<Vec<String>>().join(", ")),
+    };
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}", shape_str);
+    // magic, version and header length take 10 bytes, the whole header is padded to a multiple of 64
+    let padding = 64 - (10 + header.len() + 1) % 64;
+    header.push_str(&" ".repeat(padding % 64));
+    header.push('\n');
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for value in data {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+// Shape and data of an array of little endian f32 or f64, f64 being converted to f32
+pub fn read_npy(reader: &mut impl Read) -> std::io::Result<(Vec<usize>, Vec<f32>)> {
+    let mut preamble = [0u8; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..6] != MAGIC {
+        return Err(invalid_data("not a .npy file".to_string()));
+    }
+    let header_len = match preamble[6] {
+        1 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        version => return Err(invalid_data(format!(".npy version {} is not supported", version))),
+    };
+    let mut header = vec![0u8; header_len];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header).to_string();
+
+    let descr = header_value(&header, "descr")?.trim_matches(|c| c == '\'' || c == '"').to_string();
+    if header_value(&header, "fortran_order")? != "False" {
+        return Err(invalid_data("fortran ordered arrays are not supported".to_string()));
+    }
+    let shape = header_value(&header, "shape")?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(|d| d.trim())
+        .filter(|d| !d.is_empty())
+        .map(|d| d.parse::<usize>().map_err(|e| invalid_data(format!("bad shape {}: {}", d, e))))
+        .collect::<std::io::Result<Vec<usize>>>()?;
+    let count = shape.iter().product::<usize>();
+
+    let data = match descr.as_str() {
+        "<f4" => {
+            let mut bytes = vec![0u8; count * 4];
+            reader.read_exact(&mut bytes)?;
+            bytes.chunks_exact(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect()
+        }
+        "<f8" => {
+            let mut bytes = vec![0u8; count * 8];
+            reader.read_exact(&mut bytes)?;
+            bytes.chunks_exact(8).map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32).collect()
+        }
+        _ => return Err(invalid_data(format!("dtype {} is not supported, expected <f4 or <f8", descr))),
+    };
+    Ok((shape, data))
+}
+
+// Raw value of a key of the header dictionary, e.g. "(3, 4)" for 'shape'
+fn header_value<'a>(header: &'a str, key: &str) -> std::io::Result<&'a str> {
+    let start = header.find(&format!("'{}':", key))
+        .ok_or_else(|| invalid_data(format!("missing {} in .npy header", key)))? + key.len() + 3;
+    let rest = header[start..].trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|i| i + 1)
+    } else {
+        rest.find(',')
+    }.ok_or_else(|| invalid_data(format!("bad {} in .npy header", key)))?;
+    Ok(rest[..end].trim())
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
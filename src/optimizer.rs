@@ -0,0 +1,340 @@
+use std::f64::consts::PI;
+use std::path::Path;
+
+use clap::ValueEnum;
+use tch::{Device, no_grad, TchError, Tensor};
+
+use crate::alpha_zero_model::AlphaZeroModel;
+use crate::Args;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OptimizerKind {
+    // SGD with momentum
+    Sgd,
+    // Adam, weight decay added to the gradients (L2)
+    #[default]
+    Adam,
+    // Adam with decoupled weight decay
+    AdamW,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LrSchedule {
+    #[default]
+    Constant,
+    // multiply by lr_gamma every lr_step_size epochs
+    Step,
+    // cosine decay from learning_rate to min_learning_rate over the whole run
+    Cosine,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct OptimizerConfig {
+    pub kind: OptimizerKind,
+    pub learning_rate: f64,
+    pub momentum: f64,
+    pub weight_decay: f64,
+    pub schedule: LrSchedule,
+    // schedules count training epochs over the whole run, not per iteration
+    pub step_size: u64,
+    pub gamma: f64,
+    pub total_epochs: u64,
+    pub min_learning_rate: f64,
+    // linear warmup over the first optimizer steps
+    pub warmup_steps: u64,
+}
+
+impl OptimizerConfig {
+    pub fn new(learning_rate: f64) -> Self {
+        OptimizerConfig {
+            kind: OptimizerKind::Adam,
+            learning_rate,
+            momentum: 0.9,
+            weight_decay: 0.0,
+            schedule: LrSchedule::Constant,
+            step_size: 1,
+            gamma: 1.0,
+            total_epochs: 1,
+            min_learning_rate: 0.0,
+            warmup_steps: 0,
+        }
+    }
+
+    pub fn from_args(args: &Args) -> Self {
+        OptimizerConfig {
+            kind: args.optimizer,
+            learning_rate: args.learning_rate,
+            momentum: args.momentum,
+            weight_decay: args.weight_decay,
+            schedule: args.lr_schedule,
+            step_size: args.lr_step_size.max(1),
+            gamma: args.lr_gamma,
+            total_epochs: ((args.num_iterations - 1).max(1) * args.num_epochs).max(1) as u64,
+            min_learning_rate: args.min_learning_rate,
+            warmup_steps: args.lr_warmup_steps,
+        }
+    }
+}
+
+
+struct ParamState {
+    name: String,
+    var: Tensor,
+    // first moment for Adam, momentum buffer for SGD
+    exp_avg: Tensor,
+    exp_avg_sq: Tensor,
+}
+
+// Optimizer whose state lives as plain tensors so it can be saved and restored,
+// it keeps updating the model weights in place across training iterations
+pub struct Optimizer {
+    params: Vec<ParamState>,
+    config: OptimizerConfig,
+    step: i64,
+    epoch: i64,
+    beta1: f64,
+    beta2: f64,
+    eps: f64,
+}
+
+impl Optimizer {
+    pub fn new(model: &AlphaZeroModel, config: OptimizerConfig) -> Self {
+        Self::with_variables(model.named_trainable_variables(), config)
+    }
+
+    fn with_variables(variables: Vec<(String, Tensor)>, config: OptimizerConfig) -> Self {
+        let params = variables.into_iter()
+            .map(|(name, var)| ParamState {
+                name,
+                exp_avg: var.zeros_like(),
+                exp_avg_sq: var.zeros_like(),
+                var,
+            })
+            .collect();
+        Optimizer {
+            params,
+            config,
+            step: 0,
+            epoch: 0,
+            beta1: 0.9,
+            beta2: 0.999,
+            eps: 1e-8,
+        }
+    }
+
+    // Learning rate of the next step
+    pub fn learning_rate(&self) -> f64 {
+        let config = &self.config;
+        let scheduled = match config.schedule {
+            LrSchedule::Constant => config.learning_rate,
+            LrSchedule::Step => config.learning_rate * config.gamma.powf((self.epoch as u64 / config.step_size) as f64),
+            LrSchedule::Cosine => {
+                let progress = (self.epoch as f64 / config.total_epochs as f64).min(1.0);
+                config.min_learning_rate + (config.learning_rate - config.min_learning_rate) * 0.5 * (1.0 + (PI * progress).cos())
+            }
+        };
+        if (self.step as u64) < config.warmup_steps {
+            scheduled * (self.step + 1) as f64 / config.warmup_steps as f64
+        } else {
+            scheduled
+        }
+    }
+
+    pub fn end_epoch(&mut self) {
+        self.epoch += 1;
+    }
+
+    pub fn zero_grad(&mut self) {
+        for param in self.params.iter_mut() {
+            param.var.zero_grad();
+        }
+    }
+
+    pub fn step(&mut self) {
+        let learning_rate = self.learning_rate();
+        let OptimizerConfig { kind, momentum, weight_decay, .. } = self.config;
+        self.step += 1;
+        let bias_correction1 = 1.0 - self.beta1.powi(self.step as i32);
+        let bias_correction2 = 1.0 - self.beta2.powi(self.step as i32);
+        no_grad(|| {
+            for param in self.params.iter_mut() {
+                let grad = param.var.grad();
+                if !grad.defined() {
+                    continue;
+                }
+                let grad = if weight_decay == 0.0 {
+                    grad
+                } else if kind == OptimizerKind::AdamW {
+                    let _ = param.var.g_mul_scalar_(1.0 - learning_rate * weight_decay);
+                    grad
+                } else {
+                    grad + &param.var * weight_decay
+                };
+                let update = match kind {
+                    OptimizerKind::Sgd => {
+                        param.exp_avg = &param.exp_avg * momentum + &grad;
+                        &param.exp_avg * learning_rate
+                    }
+                    OptimizerKind::Adam | OptimizerKind::AdamW => {
+                        param.exp_avg = &param.exp_avg * self.beta1 + &grad * (1.0 - self.beta1);
+                        param.exp_avg_sq = &param.exp_avg_sq * self.beta2 + (&grad * &grad) * (1.0 - self.beta2);
+                        let denom = (&param.exp_avg_sq / bias_correction2).sqrt() + self.eps;
+                        (&param.exp_avg / bias_correction1) / denom * learning_rate
+                    }
+                };
+                let _ = param.var.g_sub_(&update);
+            }
+        });
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), TchError> {
+        let mut tensors = vec![
+            ("step".to_string(), Tensor::from(self.step)),
+            ("epoch".to_string(), Tensor::from(self.epoch)),
+        ];
+        for param in &self.params {
+            tensors.push((format!("exp_avg.{}", param.name), param.exp_avg.to_device(Device::Cpu)));
+            tensors.push((format!("exp_avg_sq.{}", param.name), param.exp_avg_sq.to_device(Device::Cpu)));
+        }
+        Tensor::write_safetensors(&tensors, path)
+    }
+
+    pub fn load(&mut self, path: &Path) -> Result<(), TchError> {
+        for (name, tensor) in Tensor::read_safetensors(path)? {
+            if name == "step" {
+                self.step = tensor.int64_value(&[]);
+            } else if name == "epoch" {
+                self.epoch = tensor.int64_value(&[]);
+            } else if let Some(param_name) = name.strip_prefix("exp_avg_sq.") {
+                if let Some(param) = self.params.iter_mut().find(|p| p.name == param_name) {
+                    param.exp_avg_sq = tensor.to_device(param.var.device());
+                }
+            } else if let Some(param_name) = name.strip_prefix("exp_avg.") {
+                if let Some(param) = self.params.iter_mut().find(|p| p.name == param_name) {
+                    param.exp_avg = tensor.to_device(param.var.device());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tch::Kind;
+
+    use super::*;
+    use crate::utils::test_dir;
+
+    const START: [f64; 2] = [1.0, -2.0];
+
+    fn optimizer(config: OptimizerConfig) -> Optimizer {
+        let var = Tensor::from_slice(&START).set_requires_grad(true);
+        Optimizer::with_variables(vec![("w".to_string(), var)], config)
+    }
+
+    // One step on a loss whose gradient is gradient
+    fn step(optimizer: &mut Optimizer, gradient: [f64; 2]) {
+        optimizer.zero_grad();
+        (&optimizer.params[0].var * Tensor::from_slice(&gradient)).sum(Kind::Double).backward();
+        optimizer.step();
+    }
+
+    fn weights(optimizer: &Optimizer) -> Vec<f64> {
+        Vec::<f64>::try_from(optimizer.params[0].var.detach()).unwrap()
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn adam_steps_match_the_closed_form_update() {
+        let (lr, beta1, beta2, eps) = (0.1, 0.9, 0.999, 1e-8);
+        let gradients = [[3.0, 0.5], [-1.0, 2.0]];
+        let mut adam = optimizer(OptimizerConfig::new(lr));
+        let (mut expected, mut m, mut v) = (START, [0.0; 2], [0.0; 2]);
+        for (t, gradient) in gradients.iter().enumerate() {
+            step(&mut adam, *gradient);
+            let t = (t + 1) as i32;
+            for i in 0..2 {
+                m[i] = beta1 * m[i] + (1.0 - beta1) * gradient[i];
+                v[i] = beta2 * v[i] + (1.0 - beta2) * gradient[i] * gradient[i];
+                let (m_hat, v_hat) = (m[i] / (1.0 - beta1.powi(t)), v[i] / (1.0 - beta2.powi(t)));
+                expected[i] -= lr * m_hat / (v_hat.sqrt() + eps);
+            }
+            assert_close(&weights(&adam), &expected);
+        }
+    }
+
+    #[test]
+    fn sgd_adds_weight_decay_to_the_gradient_with_momentum() {
+        let config = OptimizerConfig { kind: OptimizerKind::Sgd, momentum: 0.5, weight_decay: 0.1, ..OptimizerConfig::new(0.1) };
+        let mut sgd = optimizer(config);
+        step(&mut sgd, [3.0, 0.5]);
+        let buffer = [3.0 + 0.1 * START[0], 0.5 + 0.1 * START[1]];
+        let after_one = [START[0] - 0.1 * buffer[0], START[1] - 0.1 * buffer[1]];
+        assert_close(&weights(&sgd), &after_one);
+        step(&mut sgd, [3.0, 0.5]);
+        let buffer = [0.5 * buffer[0] + 3.0 + 0.1 * after_one[0], 0.5 * buffer[1] + 0.5 + 0.1 * after_one[1]];
+        assert_close(&weights(&sgd), &[after_one[0] - 0.1 * buffer[0], after_one[1] - 0.1 * buffer[1]]);
+    }
+
+    #[test]
+    fn adamw_decays_the_weights_apart_from_the_gradient() {
+        let config = OptimizerConfig { kind: OptimizerKind::AdamW, weight_decay: 0.5, ..OptimizerConfig::new(0.1) };
+        let mut adamw = optimizer(config);
+        step(&mut adamw, [3.0, 0.5]);
+        // the first bias corrected Adam step is lr * gradient / (|gradient| + eps)
+        let expected = [0, 1].map(|i| START[i] * (1.0 - 0.1 * 0.5) - 0.1 * [3.0, 0.5][i] / ([3.0f64, 0.5][i] + 1e-8));
+        assert_close(&weights(&adamw), &expected);
+    }
+
+    #[test]
+    fn schedules_set_the_learning_rate_of_each_epoch_and_step() {
+        let mut step_schedule = optimizer(OptimizerConfig { schedule: LrSchedule::Step, step_size: 2, gamma: 0.5, ..OptimizerConfig::new(0.1) });
+        let mut rates = vec![];
+        for _ in 0..5 {
+            rates.push(step_schedule.learning_rate());
+            step_schedule.end_epoch();
+        }
+        assert_close(&rates, &[0.1, 0.1, 0.05, 0.05, 0.025]);
+
+        let mut cosine = optimizer(OptimizerConfig { schedule: LrSchedule::Cosine, total_epochs: 4, min_learning_rate: 0.2, ..OptimizerConfig::new(1.0) });
+        let mut rates = vec![];
+        for _ in 0..6 {
+            rates.push(cosine.learning_rate());
+            cosine.end_epoch();
+        }
+        let half = 0.2 + 0.8 * 0.5 * (1.0 + (PI / 4.0).cos());
+        assert_close(&rates, &[1.0, half, 0.6, 0.2 + 0.2 + 0.8 - half, 0.2, 0.2]);
+
+        // steps without gradients only move the warmup forward
+        let mut warmup = optimizer(OptimizerConfig { warmup_steps: 4, ..OptimizerConfig::new(0.1) });
+        let mut rates = vec![];
+        for _ in 0..5 {
+            rates.push(warmup.learning_rate());
+            warmup.step();
+        }
+        assert_close(&rates, &[0.025, 0.05, 0.075, 0.1, 0.1]);
+    }
+
+    #[test]
+    fn saved_moments_and_counters_load_back() {
+        let mut adam = optimizer(OptimizerConfig::new(0.1));
+        step(&mut adam, [3.0, 0.5]);
+        step(&mut adam, [-1.0, 2.0]);
+        adam.end_epoch();
+        let path = test_dir("optimizer").join("optimizer.safetensors");
+        adam.save(&path).unwrap();
+
+        let mut loaded = optimizer(OptimizerConfig::new(0.1));
+        loaded.load(&path).unwrap();
+        assert_eq!((loaded.step, loaded.epoch), (2, 1));
+        assert!(loaded.params[0].exp_avg.equal(&adam.params[0].exp_avg));
+        assert!(loaded.params[0].exp_avg_sq.equal(&adam.params[0].exp_avg_sq));
+    }
+}
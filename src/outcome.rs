@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use battlesnake_game_types::types::{Move, SnakeId};
+use battlesnake_game_types::wire_representation::Position;
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::game::GameBoard;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndCause {
+    Wall,
+    SelfCollision,
+    BodyCollision,
+    HeadToHead,
+    Starvation,
+    HealthThreshold,
+    TurnLimit,
+    Unknown,
+}
+
+impl Display for EndCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            EndCause::Wall => "wall",
+            EndCause::SelfCollision => "self_collision",
+            EndCause::BodyCollision => "body_collision",
+            EndCause::HeadToHead => "head_to_head",
+            EndCause::Starvation => "starvation",
+            EndCause::HealthThreshold => "health_threshold",
+            EndCause::TurnLimit => "turn_limit",
+            EndCause::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+
+// Result of a finished game from one player's point of view.
+// The cause is the loser's death cause (own cause for a loss or a draw)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameOutcome {
+    Win { turn: u32, cause: EndCause },
+    Loss { turn: u32, cause: EndCause },
+    Draw { turn: u32, cause: EndCause },
+}
+
+impl GameOutcome {
+    pub fn turn(&self) -> u32 {
+        match self {
+            GameOutcome::Win { turn, .. } | GameOutcome::Loss { turn, .. } | GameOutcome::Draw { turn, .. } => *turn,
+        }
+    }
+
+    pub fn cause(&self) -> EndCause {
+        match self {
+            GameOutcome::Win { cause, .. } | GameOutcome::Loss { cause, .. } | GameOutcome::Draw { cause, .. } => *cause,
+        }
+    }
+
+    pub fn value(&self, draw_value: f32) -> f32 {
+        match self {
+            GameOutcome::Win { .. } => 1.0,
+            GameOutcome::Loss { .. } => -1.0,
+            GameOutcome::Draw { .. } => draw_value,
+        }
+    }
+
+    // Same game seen from the other player
+    pub fn flipped(&self) -> GameOutcome {
+        match *self {
+            GameOutcome::Win { turn, cause } => GameOutcome::Loss { turn, cause },
+            GameOutcome::Loss { turn, cause } => GameOutcome::Win { turn, cause },
+            draw => draw,
+        }
+    }
+}
+
+
+// Why each snake died between `before` and `after` given the joint moves, None if still alive
+pub fn get_death_causes<B: GameBoard>(before: &B, after: &B, moves: &[Move; 2]) -> [Option<EndCause>; 2] {
+    let mut causes = [None, None];
+    let ids = [SnakeId(0), SnakeId(1)];
+    if ids.iter().all(|id| !before.is_alive(id) || after.is_alive(id)) {
+        return causes;
+    }
+    let new_heads = ids.map(|id| {
+        if before.is_alive(&id) {
+            Some(before.get_head_as_position(&id).add_vec(moves[id.0 as usize].to_vector()))
+        } else {
+            None
+        }
+    });
+    let moving_bodies = ids.map(|id| {
+        if !before.is_alive(&id) {
+            return vec![];
+        }
+        let mut body = before.get_snake_body_vec(&id).into_iter().map(|cell| before.position_from_native(cell)).collect_vec();
+        let len = body.len();
+        // the tail leaves its cell unless it is stacked
+        if len < 2 || body[len - 1] != body[len - 2] {
+            body.pop();
+        }
+        body
+    });
+    let foods = before.get_all_food_as_positions();
+    let size = B::SIZE as i32;
+    let off_board = |p: &Position| p.x < 0 || p.y < 0 || p.x >= size || p.y >= size;
+
+    for (idx, id) in ids.iter().enumerate() {
+        if !before.is_alive(id) || after.is_alive(id) {
+            continue;
+        }
+        let other = 1 - idx;
+        let head = new_heads[idx].unwrap();
+        causes[idx] = Some(if off_board(&head) {
+            EndCause::Wall
+        } else if before.get_health(id) <= 1 && !foods.contains(&head) {
+            EndCause::Starvation
+        } else if moving_bodies[idx].contains(&head) {
+            EndCause::SelfCollision
+        } else if moving_bodies[other].contains(&head) {
+            EndCause::BodyCollision
+        } else if new_heads[other] == Some(head) {
+            EndCause::HeadToHead
+        } else {
+            EndCause::Unknown
+        });
+    }
+    causes
+}
+
+
+// Aggregated outcomes of many games, seen from the same player
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct OutcomeStats {
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+    pub total_turns: u64,
+    pub causes: HashMap<EndCause, usize>,
+}
+
+impl OutcomeStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, outcome: &GameOutcome) {
+        match outcome {
+            GameOutcome::Win { .. } => self.wins += 1,
+            GameOutcome::Loss { .. } => self.losses += 1,
+            GameOutcome::Draw { .. } => self.draws += 1,
+        }
+        self.total_turns += outcome.turn() as u64;
+        *self.causes.entry(outcome.cause()).or_insert(0) += 1;
+    }
+
+    pub fn games(&self) -> usize {
+        self.wins + self.losses + self.draws
+    }
+
+    pub fn avg_turns(&self) -> f32 {
+        if self.games() == 0 {
+            0.0
+        } else {
+            self.total_turns as f32 / self.games() as f32
+        }
+    }
+}
+
+impl Display for OutcomeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let causes = self.causes.iter()
+            .sorted()
+            .map(|(cause, count)| format!("{}: {}", cause, count))
+            .join(", ");
+        write!(f, "W/L/D : {}/{}/{} ; AVG TURNS : {:.2} ; CAUSES : {}", self.wins, self.losses, self.draws, self.avg_turns(), causes)
+    }
+}
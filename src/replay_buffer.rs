@@ -0,0 +1,205 @@
+use rand::Rng;
+use rand::seq::index;
+
+use crate::alpha_zero_model::AlphaZeroModel;
+use crate::Args;
+use crate::config::NUM_SYMMETRIES;
+use crate::dataset::{Dataset, ExamplesDataset};
+
+// Chooses the positions trained on from the saved iterations.
+// With the defaults every position of every loaded iteration is used once, as before
+#[derive(Clone, Copy, Debug)]
+pub struct ReplayBuffer {
+    // share of positions kept per iteration of age, 1.0 keeps old iterations whole
+    pub recency_decay: f32,
+    // max positions drawn from a single iteration
+    pub iteration_quota: Option<usize>,
+    // exponent of the value error priority, 0.0 samples uniformly
+    pub value_priority: f32,
+    // KataGo like growing window, see window_size
+    pub window_alpha: Option<f32>,
+    pub window_beta: f32,
+    pub window_min: f32,
+}
+
+impl ReplayBuffer {
+    pub fn from_args(args: &Args) -> Self {
+        ReplayBuffer {
+            recency_decay: args.replay_recency_decay,
+            iteration_quota: args.replay_iteration_quota,
+            value_priority: args.replay_value_priority,
+            window_alpha: args.replay_window_alpha,
+            window_beta: args.replay_window_beta,
+            window_min: args.replay_window_min,
+        }
+    }
+
+    // Number of most recent iterations trained on after `iterations` iterations of self play:
+    // min * (1 + beta * ((iterations / min)^alpha - 1) / alpha), all of them while fewer than min
+    pub fn window_size(&self, iterations: usize) -> usize {
+        let window = match self.window_alpha {
+            Some(alpha) if iterations as f32 > self.window_min => {
+                let (n, c) = (iterations as f32, self.window_min);
+                (c * (1.0 + self.window_beta * ((n / c).powf(alpha) - 1.0) / alpha)).floor() as usize
+            }
+            _ => iterations,
+        };
+        window.clamp(1, iterations.max(1))
+    }
+
+    // Positions drawn from the examples files of the dataset, each given by the index of its first sample
+    // (a position being NUM_SYMMETRIES samples in a row). iterations is the number of self play iterations run so far,
+    // older ones may already be deleted
+    pub fn sample(&self, dataset: &ExamplesDataset, iterations: usize, model: &AlphaZeroModel, batch_size: usize, rng: &mut impl Rng) -> Vec<usize> {
+        self.sample_with_values(dataset, iterations, rng, |positions| model.predict_values(dataset, positions, batch_size))
+    }
+
+    // sample with the predicted values of the positions of an iteration given by predict_values
+    fn sample_with_values(&self, dataset: &ExamplesDataset, iterations: usize, rng: &mut impl Rng, predict_values: impl Fn(&[usize]) -> Vec<f32>) -> Vec<usize> {
+        let file_ranges = dataset.file_ranges();
+        let window = self.window_size(iterations).min(file_ranges.len());
+        let mut positions = vec![];
+        for (age, range) in file_ranges[file_ranges.len() - window..].iter().rev().enumerate() {
+            let iteration_positions = range.clone().step_by(NUM_SYMMETRIES).collect::<Vec<usize>>();
+            let len = iteration_positions.len();
+            let mut amount = (len as f32 * self.recency_decay.powi(age as i32)).round() as usize;
+            if let Some(quota) = self.iteration_quota {
+                amount = amount.min(quota);
+            }
+            amount = amount.min(len);
+            if amount == len && self.value_priority == 0.0 {
+                positions.extend(iteration_positions);
+                continue;
+            }
+            let ids = if self.value_priority == 0.0 {
+                index::sample(rng, len, amount)
+            } else {
+                let priorities = self.value_priorities(dataset, &iteration_positions, predict_values(&iteration_positions));
+                index::sample_weighted(rng, len, |i| priorities[i], amount).unwrap()
+            };
+            positions.extend(ids.into_iter().map(|i| iteration_positions[i]));
+        }
+        println!("REPLAY : {} positions from the last {} iterations", positions.len(), window);
+        positions
+    }
+
+    // (|predicted value - target value| + eps)^value_priority, predicted on the first symmetry of each position
+    fn value_priorities(&self, dataset: &ExamplesDataset, positions: &[usize], values: Vec<f32>) -> Vec<f32> {
+        positions.iter().zip(values)
+            .map(|(&position, value)| ((value - dataset.value(position)).abs() + 1e-3).powf(self.value_priority))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::examples_file::{Compression, write_examples};
+    use crate::game::Sample;
+    use crate::utils::test_dir;
+
+    const DEFAULT: ReplayBuffer = ReplayBuffer {
+        recency_decay: 1.0,
+        iteration_quota: None,
+        value_priority: 0.0,
+        window_alpha: None,
+        window_beta: 0.4,
+        window_min: 4.0,
+    };
+
+    // One examples file per iteration, oldest first, values[i][j] being the value of the j-th position of iteration i
+    fn dataset(name: &str, values: &[Vec<f32>]) -> ExamplesDataset {
+        let dir = test_dir(name);
+        let paths = values.iter().enumerate().map(|(i, values)| {
+            let samples = values.iter()
+                .flat_map(|&value| (0..NUM_SYMMETRIES).map(move |_| (vec![vec![0.0; 7]; 7], [0.25; 4], value)))
+                .collect::<Vec<Sample>>();
+            let path = dir.join(format!("checkpoint_{}.examples", i));
+            write_examples(&path, &samples, None, 7, Compression::None).unwrap();
+            path
+        }).collect::<Vec<_>>();
+        ExamplesDataset::open(&paths, 7).unwrap()
+    }
+
+    // Positions drawn from each iteration, oldest first, after checking they are distinct position starts
+    fn counts(dataset: &ExamplesDataset, positions: &[usize]) -> Vec<usize> {
+        let mut sorted = positions.to_vec();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), positions.len());
+        assert!(positions.iter().all(|position| position % NUM_SYMMETRIES == 0));
+        dataset.file_ranges().iter()
+            .map(|range| positions.iter().filter(|position| range.contains(position)).count())
+            .collect()
+    }
+
+    fn sample(buffer: ReplayBuffer, dataset: &ExamplesDataset, iterations: usize) -> Vec<usize> {
+        buffer.sample_with_values(dataset, iterations, &mut StdRng::seed_from_u64(1), |positions| vec![0.0; positions.len()])
+    }
+
+    #[test]
+    fn window_grows_like_katago() {
+        assert_eq!(DEFAULT.window_size(0), 1);
+        assert_eq!(DEFAULT.window_size(100), 100);
+        let buffer = ReplayBuffer { window_alpha: Some(0.75), ..DEFAULT };
+        // every iteration while at most window_min
+        assert_eq!(buffer.window_size(3), 3);
+        assert_eq!(buffer.window_size(4), 4);
+        // 4 * (1 + 0.4 * ((n / 4)^0.75 - 1) / 0.75)
+        assert_eq!(buffer.window_size(5), 4);
+        assert_eq!(buffer.window_size(16), 7);
+        assert_eq!(buffer.window_size(100), 25);
+    }
+
+    #[test]
+    fn defaults_keep_every_position() {
+        let dataset = dataset("replay_defaults", &[vec![0.0; 2], vec![0.0; 3], vec![0.0; 4]]);
+        assert_eq!(counts(&dataset, &sample(DEFAULT, &dataset, 3)), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn window_keeps_the_last_iterations() {
+        let dataset = dataset("replay_window", &[vec![0.0; 2], vec![0.0; 3], vec![0.0; 4]]);
+        // a window of window_min iterations when beta is 0
+        let buffer = ReplayBuffer { window_alpha: Some(0.5), window_beta: 0.0, window_min: 2.0, ..DEFAULT };
+        assert_eq!(counts(&dataset, &sample(buffer, &dataset, 3)), vec![0, 3, 4]);
+        // iterations whose examples were deleted do not shrink the window below the files left
+        assert_eq!(counts(&dataset, &sample(DEFAULT, &dataset, 10)), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn recency_decay_shrinks_older_iterations() {
+        let dataset = dataset("replay_decay", &[vec![0.0; 8], vec![0.0; 8], vec![0.0; 8], vec![0.0; 8]]);
+        let buffer = ReplayBuffer { recency_decay: 0.5, ..DEFAULT };
+        assert_eq!(counts(&dataset, &sample(buffer, &dataset, 4)), vec![1, 2, 4, 8]);
+    }
+
+    #[test]
+    fn quota_caps_each_iteration() {
+        let dataset = dataset("replay_quota", &[vec![0.0; 2], vec![0.0; 5], vec![0.0; 8]]);
+        let buffer = ReplayBuffer { iteration_quota: Some(3), ..DEFAULT };
+        assert_eq!(counts(&dataset, &sample(buffer, &dataset, 3)), vec![2, 3, 3]);
+        let buffer = ReplayBuffer { recency_decay: 0.5, iteration_quota: Some(3), ..DEFAULT };
+        assert_eq!(counts(&dataset, &sample(buffer, &dataset, 3)), vec![1, 3, 3]);
+    }
+
+    #[test]
+    fn value_priority_favours_badly_predicted_positions() {
+        // predicted values are 0, so only the positions of value 1 have an error
+        let values = (0..16).map(|i| (i % 2) as f32).collect::<Vec<f32>>();
+        let dataset = dataset("replay_priority", &[values.clone(), values]);
+        let buffer = ReplayBuffer { iteration_quota: Some(8), value_priority: 4.0, ..DEFAULT };
+        let positions = sample(buffer, &dataset, 2);
+        assert_eq!(counts(&dataset, &positions), vec![8, 8]);
+        assert!(positions.iter().all(|&position| dataset.value(position) == 1.0));
+        // without priority the quota draws both kinds of positions
+        let buffer = ReplayBuffer { iteration_quota: Some(8), ..DEFAULT };
+        let positions = sample(buffer, &dataset, 2);
+        assert!(positions.iter().any(|&position| dataset.value(position) == 0.0));
+    }
+}
+
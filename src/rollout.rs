@@ -0,0 +1,300 @@
+use battlesnake_game_types::types::{Move, SnakeId};
+use battlesnake_game_types::wire_representation::Position;
+use clap::ValueEnum;
+use rand::Rng;
+use rand::rngs::ThreadRng;
+use rand::seq::{IteratorRandom, SliceRandom};
+
+use crate::Args;
+use crate::game::{GameBoard, MoveBattleSnake};
+use crate::heuristics::{free_neighbours, head_to_head_risk, HeuristicEvaluator};
+
+// Chooses the joint move of both snakes at each step of a rollout
+pub trait RolloutPolicy<B: GameBoard> {
+    fn choose_moves(&self, board: &B, rng: &mut ThreadRng) -> [Move; 2];
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum RolloutPolicyKind {
+    Random,
+    #[default]
+    Safe,
+    EpsilonGreedy,
+}
+
+
+// Uniformly random joint move among the reasonable moves of both snakes
+#[derive(Clone, Copy, Debug)]
+pub struct RandomPolicy;
+
+impl<B: GameBoard> RolloutPolicy<B> for RandomPolicy {
+    fn choose_moves(&self, board: &B, rng: &mut ThreadRng) -> [Move; 2] {
+        board.get_available_moves().into_iter().choose(rng).unwrap()
+    }
+}
+
+
+// Random move among the ones that do not die right away nor risk a lost head to head, toward the nearest food when hungry
+#[derive(Clone, Copy, Debug)]
+pub struct SafePolicy {
+    pub hungry_health: u8,
+}
+
+impl SafePolicy {
+    // Moves of snake_id left by the filters, in order of preference, falling back on the reasonable moves
+    pub fn candidate_moves<B: GameBoard>(&self, board: &B, snake_id: SnakeId, reasonable_moves: &[Move]) -> Vec<Move> {
+        if !board.is_alive(&snake_id) {
+            return reasonable_moves.to_vec();
+        }
+        let opponent_id = SnakeId(1 - snake_id.0);
+        let head = board.get_head_as_position(&snake_id);
+        let target = |mv: &Move| {
+            let vector = mv.to_vector();
+            Position { x: head.x + vector.x as i32, y: head.y + vector.y as i32 }
+        };
+        let free_cells = free_neighbours(board, &head);
+        let alive = reasonable_moves.iter().copied().filter(|mv| free_cells.contains(&target(mv))).collect::<Vec<Move>>();
+        if alive.is_empty() {
+            return reasonable_moves.to_vec();
+        }
+        let safe = alive.iter().copied().filter(|mv| !head_to_head_risk(board, snake_id, opponent_id, &target(mv))).collect::<Vec<Move>>();
+        let safe = if safe.is_empty() { alive } else { safe };
+
+        let foods = board.get_all_food_as_positions();
+        if board.get_health(&snake_id) > self.hungry_health || foods.is_empty() {
+            return safe;
+        }
+        let food_distance = |mv: &Move| {
+            let cell = target(mv);
+            foods.iter().map(|food| (food.x - cell.x).abs() + (food.y - cell.y).abs()).min().unwrap()
+        };
+        let closest = safe.iter().map(food_distance).min().unwrap();
+        safe.into_iter().filter(|mv| food_distance(mv) == closest).collect()
+    }
+}
+
+impl<B: GameBoard> RolloutPolicy<B> for SafePolicy {
+    fn choose_moves(&self, board: &B, rng: &mut ThreadRng) -> [Move; 2] {
+        let reasonable_moves = board.get_moves_for_each_snake();
+        [0, 1].map(|idx| *self.candidate_moves(board, SnakeId(idx as u8), &reasonable_moves[idx]).choose(rng).unwrap())
+    }
+}
+
+
+// Safe move maximizing the heuristic one move ahead, against a safe move of the opponent, or a random safe move with
+// probability epsilon
+#[derive(Clone, Copy, Debug)]
+pub struct EpsilonGreedyPolicy {
+    pub epsilon: f32,
+    pub safe: SafePolicy,
+    pub evaluator: HeuristicEvaluator,
+}
+
+impl<B: GameBoard> RolloutPolicy<B> for EpsilonGreedyPolicy {
+    fn choose_moves(&self, board: &B, rng: &mut ThreadRng) -> [Move; 2] {
+        let reasonable_moves = board.get_moves_for_each_snake();
+        let candidates = [0, 1].map(|idx| self.safe.candidate_moves(board, SnakeId(idx as u8), &reasonable_moves[idx]));
+        let safe_moves = candidates.clone().map(|moves| *moves.choose(rng).unwrap());
+        [0, 1].map(|idx| {
+            if rng.gen::<f32>() < self.epsilon || candidates[idx].len() == 1 {
+                return safe_moves[idx];
+            }
+            let snake_id = SnakeId(idx as u8);
+            let score = |mv: &Move| {
+                let mut moves = safe_moves;
+                moves[idx] = *mv;
+                let next = board.simulate_moves(&moves, true);
+                if next.is_alive(&snake_id) { self.evaluator.evaluate(&next, snake_id) } else { -1.0 }
+            };
+            candidates[idx].iter().map(|mv| (score(mv), *mv)).max_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap()).unwrap().1
+        })
+    }
+}
+
+
+// How normal_mcts plays out a leaf and scores it
+#[derive(Clone, Copy, Debug)]
+pub struct RolloutConfig {
+    pub policy: RolloutPolicyKind,
+    pub hungry_health: u8,
+    pub epsilon: f32,
+    // joint moves after which a rollout stops and the board is scored by the evaluator, None plays to the end
+    pub max_depth: Option<usize>,
+    pub evaluator: HeuristicEvaluator,
+}
+
+impl RolloutConfig {
+    pub fn from_args(args: &Args) -> Self {
+        RolloutConfig {
+            policy: args.rollout_policy,
+            hungry_health: args.rollout_hungry_health,
+            epsilon: args.rollout_epsilon,
+            max_depth: args.rollout_max_depth,
+            evaluator: HeuristicEvaluator::from_args(args),
+        }
+    }
+
+    // Value of the rollout from state for player_id in [-1, 1]: 1 for a win, -1 for a loss, 0 for a draw
+    pub fn run<B: GameBoard>(&self, state: B, player_id: &SnakeId) -> f32 {
+        let safe = SafePolicy { hungry_health: self.hungry_health };
+        match self.policy {
+            RolloutPolicyKind::Random => self.run_with(&RandomPolicy, state, player_id),
+            RolloutPolicyKind::Safe => self.run_with(&safe, state, player_id),
+            RolloutPolicyKind::EpsilonGreedy => self.run_with(&EpsilonGreedyPolicy { epsilon: self.epsilon, safe, evaluator: self.evaluator }, state, player_id),
+        }
+    }
+
+    pub fn run_with<B: GameBoard, P: RolloutPolicy<B>>(&self, policy: &P, state: B, player_id: &SnakeId) -> f32 {
+        let mut current_state = state;
+        let mut rng = rand::thread_rng();
+        let mut depth = 0;
+        while !current_state.is_over() {
+            if self.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+                return self.evaluator.evaluate(&current_state, *player_id);
+            }
+            let chosen_moves = policy.choose_moves(&current_state, &mut rng);
+            current_state = current_state.simulate_moves(&chosen_moves, true);
+            depth += 1;
+        }
+        match current_state.get_winner() {
+            Some(winner) if winner == *player_id => 1.0,
+            Some(_) => -1.0,
+            None => 0.0,
+        }
+    }
+}
+
+impl Default for RolloutConfig {
+    fn default() -> Self {
+        RolloutConfig {
+            policy: RolloutPolicyKind::default(),
+            hungry_health: 30,
+            epsilon: 0.1,
+            max_depth: None,
+            evaluator: HeuristicEvaluator::default(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::game::{Board7x7, test_board};
+
+    // Snake 0 against the left wall with its body on its right, snake 1 against the right wall with its body on its
+    // left, each left with Up and Down
+    fn walled_board() -> Board7x7 {
+        test_board(
+            r#"[{"x":0,"y":1},{"x":1,"y":1},{"x":1,"y":2}]"#, 100,
+            r#"[{"x":6,"y":5},{"x":5,"y":5},{"x":5,"y":4}]"#, 100,
+            "[]",
+        )
+    }
+
+    // Snake 0 of length 3 heading right at snake 1 of length 4, two cells apart
+    fn facing_board(health: u8, food: &str) -> Board7x7 {
+        test_board(
+            r#"[{"x":3,"y":3},{"x":2,"y":3},{"x":1,"y":3}]"#, health,
+            r#"[{"x":5,"y":3},{"x":5,"y":2},{"x":5,"y":1},{"x":5,"y":0}]"#, 100,
+            food,
+        )
+    }
+
+    fn move_set(moves: impl IntoIterator<Item=Move>) -> HashSet<Move> {
+        moves.into_iter().collect()
+    }
+
+    // Snake 0 goes up and snake 1 down, whatever the board
+    struct UpDownPolicy;
+
+    impl<B: GameBoard> RolloutPolicy<B> for UpDownPolicy {
+        fn choose_moves(&self, _board: &B, _rng: &mut ThreadRng) -> [Move; 2] {
+            [Move::Up, Move::Down]
+        }
+    }
+
+    #[test]
+    fn random_policy_draws_every_available_joint_move() {
+        let board = walled_board();
+        let mut rng = rand::thread_rng();
+        let drawn = (0..200).map(|_| RolloutPolicy::<Board7x7>::choose_moves(&RandomPolicy, &board, &mut rng)).collect::<HashSet<[Move; 2]>>();
+        assert_eq!(drawn, board.get_available_moves().into_iter().collect::<HashSet<[Move; 2]>>());
+        assert_eq!(drawn.len(), 4);
+    }
+
+    #[test]
+    fn safe_policy_avoids_walls_and_bodies() {
+        let safe = SafePolicy { hungry_health: 30 };
+        let board = walled_board();
+        let all_moves = Move::all_iter().collect::<Vec<Move>>();
+        assert_eq!(move_set(safe.candidate_moves(&board, SnakeId(0), &all_moves)), move_set([Move::Up, Move::Down]));
+        assert_eq!(move_set(safe.candidate_moves(&board, SnakeId(1), &all_moves)), move_set([Move::Up, Move::Down]));
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let moves = safe.choose_moves(&board, &mut rng);
+            assert!(moves.iter().all(|mv| [Move::Up, Move::Down].contains(mv)), "{:?}", moves);
+        }
+
+        // nothing is free around the head in the corner, the reasonable moves are kept
+        let boxed = test_board(
+            r#"[{"x":0,"y":0},{"x":1,"y":0},{"x":1,"y":1},{"x":0,"y":1}]"#, 100,
+            r#"[{"x":6,"y":5},{"x":5,"y":5},{"x":5,"y":4}]"#, 100,
+            "[]",
+        );
+        assert_eq!(safe.candidate_moves(&boxed, SnakeId(0), &[Move::Up]), vec![Move::Up]);
+    }
+
+    #[test]
+    fn safe_policy_avoids_lost_head_to_heads_and_goes_to_food_when_hungry() {
+        let safe = SafePolicy { hungry_health: 30 };
+        let all_moves = Move::all_iter().collect::<Vec<Move>>();
+        let board = facing_board(100, r#"[{"x":3,"y":5}]"#);
+        // Right meets the longer snake 1, which does not mind
+        assert_eq!(move_set(safe.candidate_moves(&board, SnakeId(0), &all_moves)), move_set([Move::Up, Move::Down]));
+        assert_eq!(move_set(safe.candidate_moves(&board, SnakeId(1), &all_moves)), move_set([Move::Up, Move::Left, Move::Right]));
+        let hungry = facing_board(10, r#"[{"x":3,"y":5}]"#);
+        assert_eq!(safe.candidate_moves(&hungry, SnakeId(0), &all_moves), vec![Move::Up]);
+    }
+
+    #[test]
+    fn epsilon_greedy_policy_plays_the_best_move_unless_exploring() {
+        // eating the food on the right is the only way to get longer, and snake 1 is too far for a head to head
+        let board = test_board(
+            r#"[{"x":3,"y":3},{"x":2,"y":3},{"x":1,"y":3}]"#, 100,
+            r#"[{"x":6,"y":6},{"x":5,"y":6},{"x":4,"y":6}]"#, 100,
+            r#"[{"x":4,"y":3}]"#,
+        );
+        let evaluator = HeuristicEvaluator { length_weight: 1.0, health_weight: 0.0, area_weight: 0.0, food_weight: 0.0, head_to_head_weight: 0.0 };
+        let greedy = EpsilonGreedyPolicy { epsilon: 0.0, safe: SafePolicy { hungry_health: 30 }, evaluator };
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            assert_eq!(greedy.choose_moves(&board, &mut rng)[0], Move::Right);
+        }
+        let exploring = EpsilonGreedyPolicy { epsilon: 1.0, ..greedy };
+        let drawn = move_set((0..200).map(|_| exploring.choose_moves(&board, &mut rng)[0]));
+        assert_eq!(drawn, move_set([Move::Up, Move::Down, Move::Right]));
+    }
+
+    #[test]
+    fn truncated_rollouts_are_scored_by_the_evaluator() {
+        let board = test_board(
+            r#"[{"x":1,"y":2},{"x":1,"y":1},{"x":1,"y":0}]"#, 50,
+            r#"[{"x":5,"y":4},{"x":5,"y":5},{"x":5,"y":6}]"#, 80,
+            "[]",
+        );
+        let config = |max_depth| RolloutConfig { max_depth: Some(max_depth), ..RolloutConfig::default() };
+        let evaluator = HeuristicEvaluator::default();
+        assert_eq!(config(0).run(board, &SnakeId(0)), evaluator.evaluate(&board, SnakeId(0)));
+
+        let moves = [Move::Up, Move::Down];
+        let after_two = board.simulate_moves(&moves, true).simulate_moves(&moves, true);
+        let value = config(2).run_with(&UpDownPolicy, board, &SnakeId(0));
+        assert_eq!(value, evaluator.evaluate(&after_two, SnakeId(0)));
+        assert!(value < 0.0);
+        assert_eq!(config(2).run_with(&UpDownPolicy, board, &SnakeId(1)), evaluator.evaluate(&after_two, SnakeId(1)));
+    }
+}
+
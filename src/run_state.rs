@@ -0,0 +1,106 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IterationRecord {
+    pub iteration: i32,
+    pub accepted: bool,
+    pub new_wins: i32,
+    pub prev_wins: i32,
+    pub draws: i32,
+}
+
+
+// Everything Coach needs to continue an interrupted run, saved as run_state.json.
+// The model and optimizer of the run are saved next to it as current.safetensors and current_optimizer.safetensors.
+// Runs resume where the state was last saved: before self play, before training or at the end of an iteration. The
+// training RNGs are reseeded from seed and the iteration, and the games continue from the saved game RNG, so a resumed
+// run plays and trains as the interrupted one would have on the same device
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunState {
+    // iteration to run next
+    pub next_iteration: i32,
+    // examples of next_iteration are already saved, resume straight to training
+    pub self_play_done: bool,
+    pub history: Vec<IterationRecord>,
+    // checkpoint file of the last accepted model
+    pub last_accepted: Option<String>,
+    // base seed of the training RNGs (torch and examples shuffling) and of the game RNG
+    pub seed: u64,
+    // game RNG of self play and arena games at the last save, see utils::with_game_rng. Run states saved before it was
+    // stored continue with a random one
+    #[serde(default = "ChaCha8Rng::from_entropy")]
+    pub game_rng: ChaCha8Rng,
+    pub example_indexes: Vec<usize>,
+    pub batch_step: u64,
+    pub epoch_step: u64,
+    // the model was pretrained on game logs
+    #[serde(default)]
+    pub pretrained: bool,
+}
+
+impl RunState {
+    pub fn new(seed: u64) -> Self {
+        RunState {
+            next_iteration: 1,
+            self_play_done: false,
+            history: vec![],
+            last_accepted: None,
+            seed,
+            game_rng: ChaCha8Rng::seed_from_u64(seed),
+            example_indexes: vec![],
+            batch_step: 0,
+            epoch_step: 0,
+            pretrained: false,
+        }
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Option<RunState>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let reader = BufReader::new(File::open(path)?);
+        Ok(Some(serde_json::from_reader(reader)?))
+    }
+
+    // Written to a temporary file first so an interruption never leaves a truncated state
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let temp_path = path.with_extension("json.tmp");
+        serde_json::to_writer_pretty(BufWriter::new(File::create(&temp_path)?), self)?;
+        std::fs::rename(temp_path, path)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::RngCore;
+
+    use super::*;
+    use crate::utils::test_dir;
+
+    #[test]
+    fn saved_game_rng_continues_where_it_stopped() {
+        let mut run_state = RunState::new(7);
+        run_state.game_rng.next_u64();
+        let path = test_dir("run_state_rng").join("run_state.json");
+        run_state.save(&path).unwrap();
+        let mut loaded = RunState::load(&path).unwrap().unwrap();
+        let draws = |rng: &mut ChaCha8Rng| (0..4).map(|_| rng.next_u64()).collect::<Vec<u64>>();
+        assert_eq!(draws(&mut loaded.game_rng), draws(&mut run_state.game_rng));
+    }
+
+    #[test]
+    fn run_state_saved_without_game_rng_loads() {
+        let mut json = serde_json::to_value(RunState::new(7)).unwrap();
+        json.as_object_mut().unwrap().remove("game_rng");
+        let path = test_dir("run_state_without_rng").join("run_state.json");
+        std::fs::write(&path, json.to_string()).unwrap();
+        assert_eq!(RunState::load(&path).unwrap().unwrap().seed, 7);
+    }
+}
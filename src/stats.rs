@@ -0,0 +1,88 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::outcome::{GameOutcome, OutcomeStats};
+
+// What happened during a single self-play episode
+#[derive(Clone, Debug, Serialize)]
+pub struct EpisodeStats {
+    // seen from player 1
+    pub outcome: GameOutcome,
+    pub food_eaten: [u32; 2],
+    pub final_lengths: [u32; 2],
+    // entropy of the MCTS visit distribution at each move
+    pub policy_entropies: Vec<f32>,
+}
+
+
+// Self-play statistics of one iteration, saved as a JSON line
+#[derive(Clone, Debug, Serialize)]
+pub struct SelfPlayStats {
+    pub iteration: i32,
+    #[serde(flatten)]
+    pub outcomes: OutcomeStats,
+    pub game_lengths: Vec<u32>,
+    pub food_eaten: Vec<[u32; 2]>,
+    pub final_lengths: Vec<[u32; 2]>,
+    pub mean_policy_entropy: f32,
+    pub policy_entropies: Vec<Vec<f32>>,
+    // running sum and count behind mean_policy_entropy
+    #[serde(skip)]
+    entropy_sum: f32,
+    #[serde(skip)]
+    entropy_count: usize,
+}
+
+impl SelfPlayStats {
+    pub fn new(iteration: i32) -> Self {
+        SelfPlayStats {
+            iteration,
+            outcomes: OutcomeStats::new(),
+            game_lengths: vec![],
+            food_eaten: vec![],
+            final_lengths: vec![],
+            mean_policy_entropy: 0.0,
+            policy_entropies: vec![],
+            entropy_sum: 0.0,
+            entropy_count: 0,
+        }
+    }
+
+    pub fn record(&mut self, episode: EpisodeStats) {
+        self.outcomes.record(&episode.outcome);
+        self.game_lengths.push(episode.outcome.turn());
+        self.food_eaten.push(episode.food_eaten);
+        self.final_lengths.push(episode.final_lengths);
+        self.entropy_sum += episode.policy_entropies.iter().sum::<f32>();
+        self.entropy_count += episode.policy_entropies.len();
+        self.policy_entropies.push(episode.policy_entropies);
+        self.mean_policy_entropy = if self.entropy_count == 0 { 0.0 } else { self.entropy_sum / self.entropy_count as f32 };
+    }
+
+    pub fn mean_food_eaten(&self) -> [f32; 2] {
+        mean_per_snake(&self.food_eaten)
+    }
+
+    pub fn mean_final_lengths(&self) -> [f32; 2] {
+        mean_per_snake(&self.final_lengths)
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::to_string(self)?)
+    }
+}
+
+fn mean_per_snake(values: &[[u32; 2]]) -> [f32; 2] {
+    if values.is_empty() {
+        return [0.0, 0.0];
+    }
+    let n = values.len() as f32;
+    [
+        values.iter().map(|v| v[0] as f32).sum::<f32>() / n,
+        values.iter().map(|v| v[1] as f32).sum::<f32>() / n,
+    ]
+}
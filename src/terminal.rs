@@ -0,0 +1,40 @@
+use crate::Args;
+use crate::heuristics::HeuristicEvaluator;
+
+// How and when a game is declared over
+#[derive(Clone, Copy, Debug)]
+pub struct TerminalConfig {
+    // A snake at or below this health is considered dead, 0 plays to the real game end
+    pub min_health_threshold: u8,
+    // Games still running after this many turns are scored by the evaluator
+    pub max_turns: Option<u32>,
+    pub evaluator: HeuristicEvaluator,
+    // Value target of a drawn game
+    pub draw_value: f32,
+}
+
+impl TerminalConfig {
+    pub fn new(min_health_threshold: u8) -> Self {
+        TerminalConfig {
+            min_health_threshold,
+            max_turns: None,
+            evaluator: HeuristicEvaluator::default(),
+            draw_value: 0.0,
+        }
+    }
+
+    pub fn from_args(args: &Args) -> Self {
+        TerminalConfig {
+            min_health_threshold: args.min_health_threshold,
+            max_turns: args.max_turns,
+            evaluator: HeuristicEvaluator::from_args(args),
+            draw_value: args.draw_value,
+        }
+    }
+}
+
+impl Default for TerminalConfig {
+    fn default() -> Self {
+        TerminalConfig::new(0)
+    }
+}
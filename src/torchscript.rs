@@ -0,0 +1,139 @@
+use std::path::Path;
+
+use tch::{CModule, Device, IValue, Kind, no_grad, TchError, Tensor};
+
+use crate::alpha_zero_model::get_base_device;
+use crate::canonical_board::CanonicalBoard;
+use crate::game::GameBoard;
+
+// Policy, value and opponent policy of a board
+pub type Prediction = ([f32; 4], f32, Option<[f32; 4]>);
+
+// Inference only model running a module written by AlphaZeroModel::export_torchscript. It has no weights of its own to
+// train, save or fold, it can only evaluate positions
+pub struct TorchScriptModel {
+    module: CModule,
+    board_size: i64,
+    // the module also returns the opponent log policies
+    opponent_policy: bool,
+}
+
+impl TorchScriptModel {
+    // Fails when the module does not return the log policies and the values, optionally followed by the opponent log
+    // policies, for boards of board_size
+    pub fn load(path: &Path, board_size: i64) -> Result<Self, TchError> {
+        let mut module = CModule::load_on_device(path, get_base_device())?;
+        module.set_eval();
+        let input = Tensor::zeros([1, board_size, board_size], (Kind::Float, get_base_device()));
+        let outputs = no_grad(|| module.forward_is(&[IValue::Tensor(input)]))?;
+        let opponent_policy = match output_tensors(outputs).map(|tensors| tensors.len()) {
+            Some(2) => false,
+            Some(3) => true,
+            _ => return Err(TchError::FileFormat(format!(
+                "{} must return a tuple of 2 or 3 tensors, the log policies, the values and optionally the opponent log policies",
+                path.display()
+            ))),
+        };
+        Ok(TorchScriptModel { module, board_size, opponent_policy })
+    }
+
+    pub fn board_size(&self) -> i64 {
+        self.board_size
+    }
+
+    pub fn has_opponent_policy(&self) -> bool {
+        self.opponent_policy
+    }
+
+    // Log policies, values and opponent log policies of a batch of boards
+    pub fn forward(&self, input: &Tensor) -> Result<(Tensor, Tensor, Option<Tensor>), TchError> {
+        let outputs = no_grad(|| self.module.forward_is(&[IValue::Tensor(input.to_device(get_base_device()))]))?;
+        // checked when loading the module, but a scripted module may return other outputs for other inputs
+        let mut tensors = output_tensors(outputs).unwrap_or_default().into_iter();
+        match (tensors.next(), tensors.next()) {
+            (Some(pi), Some(v)) => Ok((pi, v, tensors.next())),
+            _ => Err(TchError::Shape("the module did not return the log policies and the values".to_string())),
+        }
+    }
+
+    // Policy and value along with the opponent's policy when the module predicts it
+    pub fn predict_with_opponent<B: GameBoard>(&self, board: &CanonicalBoard<B>) -> Result<Prediction, TchError> {
+        let (pi, v, opponent_pi) = self.forward(&board.to_tensor())?;
+        let value = v.view(-1).to_device(Device::Cpu).try_into()?;
+        Ok((to_actions(pi)?, value, opponent_pi.map(to_actions).transpose()?))
+    }
+
+    // Policies and values of many boards in a single forward pass
+    pub fn predict_batch<B: GameBoard>(&self, boards: &[CanonicalBoard<B>]) -> Result<Vec<([f32; 4], f32)>, TchError> {
+        if boards.is_empty() {
+            return Ok(vec![]);
+        }
+        let input = Tensor::stack(&boards.iter().map(|board| board.to_tensor()).collect::<Vec<Tensor>>(), 0);
+        let (pi, v, _) = self.forward(&input)?;
+        let pis: Vec<f32> = pi.exp().view(-1).to_device(Device::Cpu).try_into()?;
+        let values: Vec<f32> = v.view(-1).to_device(Device::Cpu).try_into()?;
+        if pis.len() != 4 * boards.len() || values.len() != boards.len() {
+            return Err(TchError::Shape(format!("expected {} policies and values, got {} policy entries and {} values", boards.len(), pis.len(), values.len())));
+        }
+        Ok(pis.chunks_exact(4).zip(values).map(|(pi, value)| ([pi[0], pi[1], pi[2], pi[3]], value)).collect())
+    }
+}
+
+
+// Probabilities of the 4 moves of a single board from its log policy
+fn to_actions(pi: Tensor) -> Result<[f32; 4], TchError> {
+    let pi: Vec<f32> = pi.exp().view(-1).to_device(Device::Cpu).try_into()?;
+    pi.as_slice().try_into().map_err(|_| TchError::Shape(format!("expected a policy of 4 moves, got {}", pi.len())))
+}
+
+
+// Tensors of a module output that is a tuple of tensors
+fn output_tensors(output: IValue) -> Option<Vec<Tensor>> {
+    let IValue::Tuple(outputs) = output else { return None };
+    outputs.into_iter().map(|output| match output {
+        IValue::Tensor(tensor) => Some(tensor),
+        _ => None,
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alpha_zero_model::AlphaZeroModel;
+    use crate::game::{CanCanonical, test_board};
+    use crate::terminal::TerminalConfig;
+    use crate::utils::test_dir;
+
+    #[test]
+    fn module_predicts_like_the_model() {
+        let model = AlphaZeroModel::new(8, 7);
+        let path = test_dir("torchscript").join("model.pt");
+        model.export_torchscript(&path).unwrap();
+        let module = TorchScriptModel::load(&path, 7).unwrap();
+
+        let board = test_board(r#"[{"x":3,"y":3},{"x":3,"y":2},{"x":3,"y":1}]"#, 90, r#"[{"x":6,"y":0},{"x":5,"y":0},{"x":4,"y":0}]"#, 80, "[]")
+            .as_canonical(1, TerminalConfig::default());
+        let (pi, value) = model.predict(&board);
+        let (module_pi, module_value, _) = module.predict_with_opponent(&board).unwrap();
+        assert!(pi.iter().zip(module_pi).all(|(p, q)| (p - q).abs() < 1e-5));
+        assert!((value - module_value).abs() < 1e-5);
+        let batch = module.predict_batch(&[board, board]).unwrap();
+        assert_eq!(batch.len(), 2);
+        for (batch_pi, batch_value) in batch {
+            assert!(batch_pi.iter().zip(module_pi).all(|(p, q)| (p - q).abs() < 1e-5));
+            assert!((batch_value - module_value).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn failed_forward_passes_are_errors() {
+        let path = test_dir("torchscript_errors").join("model.pt");
+        AlphaZeroModel::new(8, 7).export_torchscript(&path).unwrap();
+        let module = TorchScriptModel::load(&path, 7).unwrap();
+        // boards of another size do not fit the fully connected layers
+        assert!(module.forward(&Tensor::zeros([1, 5, 5], (Kind::Float, Device::Cpu))).is_err());
+        assert!(TorchScriptModel::load(&path, 5).is_err());
+    }
+}
+
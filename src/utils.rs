@@ -1,7 +1,9 @@
+use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::fmt::Display;
 
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
 pub struct BoundedDeque<T> {
     pub deque: VecDeque<T>,
@@ -45,8 +47,27 @@ impl<T> BoundedDeque<T> {
 }
 
 
+thread_local! {
+    // Random draws of the games played on this thread: board setups, food spawns and move choices. Coach seeds it and
+    // keeps its state in the run state, so that a resumed run replays the same self play and arena games
+    static GAME_RNG: RefCell<ChaCha8Rng> = RefCell::new(ChaCha8Rng::from_entropy());
+}
+
+pub fn with_game_rng<T>(f: impl FnOnce(&mut ChaCha8Rng) -> T) -> T {
+    GAME_RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+pub fn set_game_rng(rng: ChaCha8Rng) {
+    GAME_RNG.with(|game_rng| *game_rng.borrow_mut() = rng);
+}
+
+// Current state of the game RNG of this thread
+pub fn game_rng() -> ChaCha8Rng {
+    GAME_RNG.with(|rng| rng.borrow().clone())
+}
+
+
 pub fn choose_index_based_on_probability(probabilities: &[f32]) -> usize {
-    let mut rng = rand::thread_rng();
     let mut cumulative_probabilities: Vec<f32> = Vec::new();
     let mut sum = 0.0;
 
@@ -57,13 +78,21 @@ pub fn choose_index_based_on_probability(probabilities: &[f32]) -> usize {
     }
 
     // Generate a random number in the range 0.0 to sum
-    let random_num = rng.gen_range(0.0..sum);
+    let random_num = with_game_rng(|rng| rng.gen_range(0.0..sum));
 
     // Find the index where the random number fits in the cumulative array
     cumulative_probabilities.iter().position(|&cum_prob| random_num <= cum_prob).unwrap()
 }
 
 
+pub fn entropy(probabilities: &[f32]) -> f32 {
+    -probabilities.iter()
+        .filter(|&&p| p > 0.0)
+        .map(|&p| p * p.ln())
+        .sum::<f32>()
+}
+
+
 pub struct AverageMeter {
     val: f32,
     avg: f32,
@@ -81,6 +110,10 @@ impl AverageMeter {
         }
     }
 
+    pub fn avg(&self) -> f32 {
+        self.avg
+    }
+
     pub fn update(&mut self, val: f32, n: usize) {
         self.val = val;
         self.sum += val * n as f32;
@@ -99,4 +132,34 @@ impl Display for AverageMeter {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:.2e}", self.avg)
     }
-}
\ No newline at end of file
+}
+
+
+// Empty directory of a test under the system temporary directory
+#[cfg(test)]
+pub fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("battlesnake_alphazero_{}_{}", name, std::process::id()));
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Board7x7, BoardInit};
+
+    #[test]
+    fn reseeded_game_rng_replays_the_same_draws() {
+        let draws = || {
+            set_game_rng(ChaCha8Rng::seed_from_u64(3));
+            let board = Board7x7::init_random_board();
+            let moves = (0..20).map(|_| choose_index_based_on_probability(&[0.25; 4])).collect::<Vec<usize>>();
+            (board, moves, game_rng())
+        };
+        assert_eq!(draws(), draws());
+    }
+}